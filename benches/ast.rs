@@ -0,0 +1,66 @@
+// Benchmarks for sharing subtrees of the AST, run with `cargo bench --features
+// bench` (needs nightly).
+
+#![feature(test)]
+
+extern crate s;
+extern crate test;
+
+use s::parser::{self, Node, NodeKind};
+use s::{lexer, expand, interpreter};
+use test::Bencher;
+
+fn parse(input: &str) -> Node {
+    parser::parse(&lexer::lex(input).unwrap()).unwrap()
+}
+
+#[bench]
+fn bench_clone(b: &mut Bencher) {
+    let mut input = String::new();
+    for i in 0..2000 {
+        input.push_str(&format!("(let value_{} 42 (fn y (+ value_{} y [1 2] {{\"k\" 'v}})))\n", i, i));
+    }
+    let ast = parse(&input);
+    b.iter(|| ast.clone());
+}
+
+#[bench]
+fn bench_expand(b: &mut Bencher) {
+    // A macro with a large body which is used many times.
+    let mut input = String::from("(macro m x [x");
+    for i in 0..200 {
+        input.push_str(&format!(" {}", i));
+    }
+    input.push_str("])\n");
+    for i in 0..200 {
+        input.push_str(&format!("(m {})\n", i));
+    }
+    let ast = parse(&input);
+    b.iter(|| expand::fold(ast.clone(), &mut expand::Unhygienic::new()));
+}
+
+// A function with a large body, passed through n calls to `id` before it
+// is called.
+fn bench_fn_input(n: usize) -> String {
+    let mut result = String::from("(let big (fn x (+ x");
+    for i in 0..200 {
+        result.push_str(&format!(" {}", i));
+    }
+    result.push_str("))\n     id (fn f f)\n     (");
+    for _ in 0..n {
+        result.push_str("(id ");
+    }
+    result.push_str("big");
+    for _ in 0..n {
+        result.push(')');
+    }
+    result.push_str(" 1))");
+    result
+}
+
+#[bench]
+fn bench_pass_fn(b: &mut Bencher) {
+    let ast = parse(&bench_fn_input(100));
+    assert!(interpreter::run_program(&ast) == vec![Node::from(NodeKind::LitNum(19901))]);
+    b.iter(|| interpreter::run_program(&ast));
+}
@@ -0,0 +1,40 @@
+// Lexer benchmarks, run with `cargo bench --features bench` (needs nightly).
+
+#![feature(test)]
+
+extern crate s;
+extern crate test;
+
+use s::lexer::{self, Lexer};
+use test::Bencher;
+
+// A large program with a bit of everything.
+fn bench_input() -> String {
+    let mut result = String::new();
+    for i in 0..2000 {
+        result.push_str(&format!("; definition number {}\n(let value_{} 42 (fn y (+ value_{} y -3.5e2 0x2a_ff))\n", i, i, i));
+        result.push_str("  (print \"a string\" \"with \\\"escapes\\\"\\n\" r\"raw\" 'quoted `(a ,b ,@c) #t #\\space [1 2] {k v}))\n");
+    }
+    result
+}
+
+#[bench]
+fn bench_lex(b: &mut Bencher) {
+    let input = bench_input();
+    b.bytes = input.len() as u64;
+    b.iter(|| lexer::lex(&input).unwrap());
+}
+
+#[bench]
+fn bench_lex_lossless(b: &mut Bencher) {
+    let input = bench_input();
+    b.bytes = input.len() as u64;
+    b.iter(|| lexer::lex_lossless(&input).unwrap());
+}
+
+#[bench]
+fn bench_lex_reader(b: &mut Bencher) {
+    let input = bench_input();
+    b.bytes = input.len() as u64;
+    b.iter(|| Lexer::from_reader(input.as_bytes()).count());
+}
@@ -1 +1,2 @@
+; The classic.
 (print "Hello world!")
@@ -1,4 +1,11 @@
+; Run with `s expand`, macros are expanded before the program is run.
 (macro foo x (let y 4 (+ y x)))
 (foo 42)
 
+#| Macros are unhygienic: the `y` in `bar` refers to the innermost `y` where
+   `bar` is used, so this evaluates to 10, not 52. |#
 (let y 42 ((macro bar x (+ y x)) (let y 0 (bar 10))))
+
+; A template macro, the quasiquoted body builds the code for the expansion.
+(macro twice f x `(,f (,f ,x)))
+(twice (fn n (+ n 1)) 40)
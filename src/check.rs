@@ -0,0 +1,202 @@
+// Static checks.
+//
+// Finds malformed code before it is run (or expanded), so every problem can be
+// reported up front rather than as a panic part way through a run. Each
+// special form checks its own shape (see SpecialForm::check), this module
+// walks the tree and checks the rules which apply everywhere.
+
+use parser::{Node, NodeKind};
+use lexer::Span;
+use forms;
+use visit::{self, Visitor};
+use std::fmt;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum CheckErrorKind {
+    // The form (e.g., `fn`) has no body.
+    MissingBody(&'static str),
+    // The form (e.g., `macro`) has no name.
+    MissingName(&'static str),
+    // Something other than a name where the form expects a name (or a
+    // formal), includes the form and what was found.
+    NotAName(&'static str, String),
+    // The form (e.g., `let`) has a name with no value.
+    BindingWithoutValue(&'static str),
+    // A keyword which is not at the start of a list.
+    MisplacedKeyword(&'static str),
+    // `,` or `,@` outside of a quasiquote.
+    MisplacedUnquote,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct CheckError {
+    pub kind: CheckErrorKind,
+    pub span: Span,
+}
+
+impl CheckError {
+    pub fn new(kind: CheckErrorKind, span: Span) -> CheckError {
+        CheckError {
+            kind: kind,
+            span: span,
+        }
+    }
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}: ", self.span));
+        match self.kind {
+            CheckErrorKind::MissingBody(form) => write!(f, "`{}` needs a body", form),
+            CheckErrorKind::MissingName(form) => write!(f, "`{}` needs a name", form),
+            CheckErrorKind::NotAName(form, ref found) => write!(f, "expected a name in `{}`, found `{}`", form, found),
+            CheckErrorKind::BindingWithoutValue(form) => write!(f, "binding without a value in `{}`", form),
+            CheckErrorKind::MisplacedKeyword(k) => write!(f, "`{}` can only be used at the start of a list", k),
+            CheckErrorKind::MisplacedUnquote => write!(f, "unquote outside of a quasiquote"),
+        }
+    }
+}
+
+// Returns every problem in node, in the order they occur.
+pub fn check(node: &Node) -> Vec<CheckError> {
+    let mut errors = Vec::new();
+    Checker {
+        errors: &mut errors,
+    }.visit_node(node);
+    errors
+}
+
+// For use by special forms: n should be a name in form. Keywords are left to
+// the check for misplaced keywords.
+pub fn expect_name(form: &'static str, n: &Node, errors: &mut Vec<CheckError>) {
+    match n.kind {
+        NodeKind::Ident(_) | NodeKind::Keyword(_) => {}
+        _ => errors.push(CheckError::new(CheckErrorKind::NotAName(form, n.to_string()), n.span)),
+    }
+}
+
+struct Checker<'a> {
+    errors: &'a mut Vec<CheckError>,
+}
+
+impl<'a> Visitor for Checker<'a> {
+    fn visit_s(&mut self, ns: &[Node], span: Span) {
+        let args = match ns.first().map(|n| &n.kind) {
+            Some(&NodeKind::Keyword(k)) => {
+                if let Some(form) = forms::lookup(k) {
+                    form.check(ns, span, self.errors);
+                }
+                &ns[1..]
+            }
+            _ => ns,
+        };
+        visit::walk_nodes(self, args)
+    }
+
+    // Quoted data is not code.
+    fn visit_quote(&mut self, _n: &Node, _span: Span) {}
+
+    fn visit_quasiquote(&mut self, n: &Node, _span: Span) {
+        Template {
+            errors: &mut *self.errors,
+            depth: 0,
+        }.visit_node(n)
+    }
+
+    fn visit_unquote(&mut self, _n: &Node, span: Span) {
+        self.errors.push(CheckError::new(CheckErrorKind::MisplacedUnquote, span));
+    }
+
+    fn visit_unquote_splicing(&mut self, _n: &Node, span: Span) {
+        self.errors.push(CheckError::new(CheckErrorKind::MisplacedUnquote, span));
+    }
+
+    fn visit_keyword(&mut self, k: &'static str, span: Span) {
+        self.errors.push(CheckError::new(CheckErrorKind::MisplacedKeyword(k), span));
+    }
+}
+
+// Only the unquoted parts of a template are code. depth is the number of
+// quasiquotes enclosing the node being visited, not counting the outermost
+// one.
+struct Template<'a> {
+    errors: &'a mut Vec<CheckError>,
+    depth: usize,
+}
+
+impl<'a> Template<'a> {
+    fn unquoted(&mut self, n: &Node) {
+        if self.depth == 0 {
+            Checker {
+                errors: &mut *self.errors,
+            }.visit_node(n);
+        } else {
+            self.depth -= 1;
+            self.visit_node(n);
+            self.depth += 1;
+        }
+    }
+}
+
+impl<'a> Visitor for Template<'a> {
+    fn visit_quasiquote(&mut self, n: &Node, _span: Span) {
+        self.depth += 1;
+        self.visit_node(n);
+        self.depth -= 1;
+    }
+
+    fn visit_unquote(&mut self, n: &Node, _span: Span) {
+        self.unquoted(n)
+    }
+
+    fn visit_unquote_splicing(&mut self, n: &Node, _span: Span) {
+        self.unquoted(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parser;
+    use lexer::{self, Span};
+
+    fn kinds(input: &str) -> Vec<CheckErrorKind> {
+        let ast = parser::parse(&lexer::lex(input).unwrap()).unwrap();
+        check(&ast).into_iter().map(|e| e.kind).collect()
+    }
+
+    #[test]
+    fn test_ok() {
+        assert!(kinds("(let x 1 y (+ x 2) (print x y)) (let 42)").is_empty());
+        assert!(kinds("((fn x y (+ x y)) 1 2) (fn 42) (print)").is_empty());
+        assert!(kinds("(macro m x `(+ ,x 1)) (m 2)").is_empty());
+        assert!(kinds("'(let fn) `(a ,(+ 1 2) `(let ,,(print 1)))").is_empty());
+    }
+
+    #[test]
+    fn test_forms() {
+        assert!(kinds("(let x 1 y (+ x y))") == vec![CheckErrorKind::BindingWithoutValue("let")]);
+        assert!(kinds("(let 1 2 3)") == vec![CheckErrorKind::NotAName("let", "1".to_owned())]);
+        assert!(kinds("(let)") == vec![CheckErrorKind::MissingBody("let")]);
+        assert!(kinds("(fn x \"y\" [z] x)") ==
+                vec![CheckErrorKind::NotAName("fn", "\"y\"".to_owned()), CheckErrorKind::NotAName("fn", "[z]".to_owned())]);
+        assert!(kinds("(fn)") == vec![CheckErrorKind::MissingBody("fn")]);
+        assert!(kinds("(macro)") == vec![CheckErrorKind::MissingName("macro")]);
+        assert!(kinds("(macro m)") == vec![CheckErrorKind::MissingBody("macro")]);
+        assert!(kinds("(macro (m) x x)") == vec![CheckErrorKind::NotAName("macro", "(m)".to_owned())]);
+        assert!(kinds("(print let)") == vec![CheckErrorKind::MisplacedKeyword("let")]);
+    }
+
+    #[test]
+    fn test_all_errors() {
+        let ast = parser::parse(&lexer::lex("(+ 1 fn)\n(let x (fn) ,y)\n`(a ,(macro))").unwrap()).unwrap();
+        let errors = check(&ast);
+        assert!(errors ==
+                vec![CheckError::new(CheckErrorKind::MisplacedKeyword("fn"), Span::new(5, 7, 1, 6)),
+                     CheckError::new(CheckErrorKind::MissingBody("fn"), Span::new(16, 20, 2, 8)),
+                     CheckError::new(CheckErrorKind::MisplacedUnquote, Span::new(21, 23, 2, 13)),
+                     CheckError::new(CheckErrorKind::MissingName("macro"), Span::new(30, 37, 3, 6))]);
+        assert!(errors[0].to_string() == "1:6: `fn` can only be used at the start of a list");
+        assert!(errors[2].to_string() == "2:13: unquote outside of a quasiquote");
+    }
+}
@@ -0,0 +1,289 @@
+// Concrete syntax tree. Unlike the AST (parser::Node), the CST keeps every byte
+// of the source, including whitespace and comments, so a program can be edited
+// and printed without disturbing the user's layout.
+
+use lexer::{self, Token, TokenKind, Span};
+use parser::{self, Node, NodeKind, ParseError, ParseErrorKind};
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Cst {
+    // A single token (which may be whitespace or a comment) and its source
+    // text.
+    Leaf(Token, String),
+    // A bracketed list (i.e., an s-expression, vector, or map), the first and
+    // last children are the delimiter leaves.
+    List(Vec<Cst>),
+}
+
+// The top level of a program.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SourceFile {
+    pub children: Vec<Cst>,
+}
+
+impl Cst {
+    // True for whitespace and comments.
+    pub fn is_trivia(&self) -> bool {
+        match *self {
+            Cst::Leaf(ref t, _) => match t.kind {
+                TokenKind::Comment(_) | TokenKind::Whitespace(_) => true,
+                _ => false,
+            },
+            Cst::List(_) => false,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match *self {
+            Cst::Leaf(ref t, _) => t.span,
+            Cst::List(ref cs) => cs[0].span().to(cs[cs.len() - 1].span()),
+        }
+    }
+
+    // The list's children, excluding the parens.
+    pub fn items(&self) -> &[Cst] {
+        match *self {
+            Cst::List(ref cs) => &cs[1..cs.len() - 1],
+            Cst::Leaf(..) => &[],
+        }
+    }
+
+    // True for `'`, `` ` ``, `,`, and `,@`.
+    pub fn is_prefix(&self) -> bool {
+        match *self {
+            Cst::Leaf(ref t, _) => match t.kind {
+                TokenKind::Quote | TokenKind::Quasiquote | TokenKind::Unquote | TokenKind::UnquoteSplicing => true,
+                _ => false,
+            },
+            Cst::List(_) => false,
+        }
+    }
+
+    // Returns None for trivia and prefixes, prefixes are lowered by the
+    // enclosing list (or SourceFile::lower) along with the following datum.
+    // The errors are a map literal with an odd number of elements and a
+    // prefix with no datum after it.
+    pub fn lower(&self) -> Result<Option<Node>, ParseError> {
+        if self.is_trivia() || self.is_prefix() {
+            return Ok(None);
+        }
+
+        match *self {
+            Cst::Leaf(ref t, _) => Ok(Some(Node::new(parser::leaf_kind(&t.kind), t.span))),
+            Cst::List(ref cs) => {
+                let open = match cs[0] {
+                    Cst::Leaf(ref t, _) => &t.kind,
+                    Cst::List(_) => unreachable!(),
+                };
+                let span = self.span();
+                let kind = try!(parser::list_kind(open, try!(lower_seq(self.items())), span));
+                Ok(Some(Node::new(kind, span)))
+            }
+        }
+    }
+}
+
+fn lower_seq(cs: &[Cst]) -> Result<Vec<Node>, ParseError> {
+    let mut result = Vec::new();
+    let mut prefixes = Vec::new();
+    for c in cs {
+        if let Cst::Leaf(ref t, _) = *c {
+            if c.is_prefix() {
+                prefixes.push(t);
+                continue;
+            }
+        }
+        if let Some(mut n) = try!(c.lower()) {
+            while let Some(t) = prefixes.pop() {
+                let span = t.span.to(n.span);
+                n = Node::new(parser::prefix_kind(&t.kind, n), span);
+            }
+            result.push(n);
+        }
+    }
+    // Like the parser, the error is at the last prefix.
+    if let Some(t) = prefixes.pop() {
+        return Err(ParseError::new(ParseErrorKind::MissingDatum(t.kind.clone()), t.span));
+    }
+    Ok(result)
+}
+
+impl SourceFile {
+    // Produces the same AST (or error) as parser::parse would for the same
+    // source.
+    pub fn lower(&self) -> Result<Node, ParseError> {
+        let ns = try!(lower_seq(&self.children));
+        let mut span = Span::new(0, 0, 1, 1);
+        if let Some(last) = self.children.iter().rev().find(|c| !c.is_trivia()) {
+            span.hi = last.span().hi;
+        }
+        Ok(Node::new(NodeKind::Program(Rc::new(ns)), span))
+    }
+}
+
+impl fmt::Display for Cst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Cst::Leaf(_, ref text) => f.write_str(text),
+            Cst::List(ref cs) => {
+                for c in cs {
+                    try!(c.fmt(f));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// Prints the source text.
+impl fmt::Display for SourceFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in &self.children {
+            try!(c.fmt(f));
+        }
+        Ok(())
+    }
+}
+
+// Returns either all the lexing errors or the first unbalanced delimiter.
+// Prefixes without a datum and odd map literals are only errors when the CST
+// is lowered.
+pub fn parse(input: &str) -> Result<SourceFile, Vec<ParseError>> {
+    let toks = try!(lexer::lex_lossless(input).map_err(|errs| errs.into_iter().map(ParseError::from).collect::<Vec<_>>()));
+
+    let mut list_stack: Vec<Vec<Cst>> = Vec::new();
+    let mut cur = Vec::new();
+    for t in toks {
+        let text = input[t.span.lo..t.span.hi].to_owned();
+        if t.kind.closing().is_some() {
+            list_stack.push(cur);
+            cur = vec![Cst::Leaf(t, text)];
+        } else if t.kind.is_closing() {
+            if list_stack.is_empty() {
+                return Err(vec![ParseError::new(ParseErrorKind::UnexpectedClose(t.kind), t.span)]);
+            }
+            if let Cst::Leaf(ref open, _) = cur[0] {
+                if open.kind.closing().as_ref() != Some(&t.kind) {
+                    let kind = ParseErrorKind::Mismatched(t.kind, open.kind.clone(), open.span);
+                    return Err(vec![ParseError::new(kind, t.span)]);
+                }
+            }
+            cur.push(Cst::Leaf(t, text));
+            let list = Cst::List(cur);
+            cur = list_stack.pop().unwrap();
+            cur.push(list);
+        } else {
+            cur.push(Cst::Leaf(t, text));
+        }
+    }
+
+    if !list_stack.is_empty() {
+        // cur is the innermost unclosed list.
+        let (open, open_span) = match cur[0] {
+            Cst::Leaf(ref t, _) => (t.kind.clone(), t.span),
+            Cst::List(_) => unreachable!(),
+        };
+        // The error is at the last token which isn't trivia, like the parser.
+        let last = cur.iter().rev().find(|c| !c.is_trivia()).unwrap();
+        let last = match *last {
+            Cst::List(ref cs) => cs[cs.len() - 1].span(),
+            ref c => c.span(),
+        };
+        return Err(vec![ParseError::new(ParseErrorKind::Unclosed(open, open_span), last)]);
+    }
+
+    Ok(SourceFile {
+        children: cur,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lexer::{self, Token, TokenKind, DUMMY_SP};
+    use symbol::Symbol;
+
+    const INPUTS: [&'static str; 8] = [
+        "",
+        "  \n",
+        "(print \"Hello world!\")",
+        "; comment\n(let x 42   #| block |#\n\t(+ x #;1 2))\r\n",
+        " a (b (c \"\\u{e9}\\\"\" r#\"raw\"#)) d ",
+        "((fn x (+ x 42))(+ 3 \"a string\"))",
+        "'a `(b ,c ,@ ;x\n (d)) ' 'e",
+        "[a {b\n [c]} ] {} (d [])",
+    ];
+
+    #[test]
+    fn test_round_trip() {
+        for input in INPUTS.iter() {
+            assert!(parse(input).unwrap().to_string() == *input);
+        }
+    }
+
+    #[test]
+    fn test_lower() {
+        for input in INPUTS.iter() {
+            let lowered = parse(input).unwrap().lower().unwrap();
+            let parsed = parser::parse(&lexer::lex(input).unwrap()).unwrap();
+            assert!(lowered == parsed);
+            assert!(lowered.span == parsed.span);
+        }
+    }
+
+    #[test]
+    fn test_structure() {
+        let file = parse("a (b c)").unwrap();
+        assert!(file.children.len() == 3);
+        assert!(file.children[1].is_trivia());
+        assert!(file.children[2].items().len() == 3);
+        assert!(file.children[2].span() == Span::new(2, 7, 1, 3));
+    }
+
+    #[test]
+    fn test_errors() {
+        // The same errors as the parser.
+        for input in ["(a [b)]", "(a\n (b) ;c\n", "a ) b"].iter() {
+            let expected = parser::parse(&lexer::lex(input).unwrap()).unwrap_err();
+            assert!(parse(input).unwrap_err() == vec![expected]);
+        }
+        // Prefixes without a datum are found when lowering.
+        for input in ["(a ')", "a '", "a ' '"].iter() {
+            let expected = parser::parse(&lexer::lex(input).unwrap()).unwrap_err();
+            assert!(parse(input).unwrap().lower().unwrap_err() == expected);
+        }
+        let errs = parse("(a \"\\q\" \"\\w\"").unwrap_err();
+        assert!(errs.len() == 2 && errs[0].kind == ParseErrorKind::Lex(lexer::LexErrorKind::UnknownEscape('q')));
+        let file = parse("{a}").unwrap();
+        assert!(file.lower().unwrap_err().kind == ParseErrorKind::KeyWithoutValue);
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut file = parse("(let  x 1\n     ; keep me\n     (+ x x))").unwrap();
+        // Rename every `x` to `foo`.
+        fn rename(c: &mut Cst) {
+            match *c {
+                Cst::Leaf(ref mut t, ref mut text) => {
+                    if t.kind == TokenKind::Name(Symbol::intern("x")) {
+                        *t = Token::new(TokenKind::Name(Symbol::intern("foo")), DUMMY_SP);
+                        *text = "foo".to_owned();
+                    }
+                }
+                Cst::List(ref mut cs) => {
+                    for c in cs {
+                        rename(c);
+                    }
+                }
+            }
+        }
+        for c in &mut file.children {
+            rename(c);
+        }
+        assert!(file.to_string() == "(let  foo 1\n     ; keep me\n     (+ foo foo))");
+        assert!(file.lower().unwrap() == program!(s!(kw!("let"), ident!("foo"), lit_num!(1),
+                                            s!(kw!("+"), ident!("foo"), ident!("foo")))));
+    }
+}
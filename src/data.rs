@@ -0,0 +1,792 @@
+// S text as a serde data format, e.g., for configuration files.
+//
+// Values are mapped to S syntax as follows:
+//
+//   bool, integers, floats, char, strings   #t, 42, 1.5, #\a, "a string"
+//   (), unit structs, None                  ()
+//   Some(x), newtype structs                x
+//   sequences and tuples                    [a b c]
+//   maps                                    {key value ...}
+//   structs                                 {field value ...}
+//   unit variants                           Variant
+//   newtype and tuple variants              (Variant a ...)
+//   struct variants                         (Variant {field value ...})
+//
+// Since Some(x) is written as x, it can't be told apart from None when x is
+// written as `()`, e.g., Some(()), Some(UnitStruct), or Some(None). Writing
+// such a value is an error rather than reading back as None.
+//
+// Struct fields and variants are written as names where possible, or as
+// strings if the name is not a valid identifier. Either is accepted when
+// reading. When reading, `(a b c)` is also accepted as a sequence, and `()` as
+// an empty one.
+//
+// Serializing produces a Node which is printed with the pretty printer.
+// Deserializing lexes and parses the input, then walks the AST, so errors give
+// the position of the offending value.
+
+use lexer::{self, TokenKind, Span, Str, DUMMY_SP};
+use parser::{self, Node, NodeKind, ParseError};
+use pretty;
+use symbol::Symbol;
+use serde::{de, ser};
+use serde::de::{DeserializeOwned, DeserializeSeed, Visitor};
+use serde::ser::Serialize;
+use std::error;
+use std::fmt;
+use std::rc::Rc;
+use std::slice;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Error {
+    pub msg: String,
+    // Where the error is in the input, if it is known.
+    pub span: Option<Span>,
+}
+
+impl Error {
+    fn new<S: Into<String>>(msg: S) -> Error {
+        Error {
+            msg: msg.into(),
+            span: None,
+        }
+    }
+
+    // Sets the span if the error doesn't already have a more precise one.
+    fn at(self, span: Span) -> Error {
+        if self.span.is_some() || span == DUMMY_SP {
+            return self;
+        }
+        Error {
+            msg: self.msg,
+            span: Some(span),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::new(e.kind.to_string()).at(e.span)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{}: {}", span, self.msg),
+            None => self.msg.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::new(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::new(msg.to_string())
+    }
+}
+
+pub fn to_node<T: Serialize + ?Sized>(value: &T) -> Result<Node, Error> {
+    value.serialize(Serializer)
+}
+
+// value as S text, pretty printed.
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, Error> {
+    let node = try!(to_node(value));
+    Ok(pretty::pretty(&node, &pretty::Config::default()))
+}
+
+pub fn from_node<T: DeserializeOwned>(node: &Node) -> Result<T, Error> {
+    T::deserialize(Deserializer::new(node))
+}
+
+// The input must be exactly one value (comments are allowed).
+pub fn from_str<T: DeserializeOwned>(input: &str) -> Result<T, Error> {
+    let toks = try!(lexer::lex(input).map_err(|errs| Error::new(errs[0].kind.to_string()).at(errs[0].span)));
+    let program = try!(parser::parse(&toks));
+    let ns = match program.kind {
+        NodeKind::Program(ns) => ns,
+        _ => unreachable!(),
+    };
+    match ns.len() {
+        0 => Err(Error::new("expected a value, found the end of the input")),
+        1 => from_node(&ns[0]),
+        _ => Err(Error::new(format!("unexpected `{}` after the value", ns[1])).at(ns[1].span)),
+    }
+}
+
+fn node(kind: NodeKind) -> Node {
+    Node::from(kind)
+}
+
+// A struct field or variant name, as an identifier if it would be read back as
+// one (or as a keyword), otherwise as a string.
+fn name(s: &str) -> Node {
+    let is_name = match lexer::lex(s) {
+        Ok(ref toks) if toks.len() == 1 => match toks[0].kind {
+            TokenKind::Name(_) | TokenKind::Keyword(_) => true,
+            _ => false,
+        },
+        _ => false,
+    };
+    if is_name {
+        node(NodeKind::Ident(Symbol::intern(s)))
+    } else {
+        node(NodeKind::LitStr(Str::new(s)))
+    }
+}
+
+
+// Serializing.
+
+pub struct Serializer;
+
+// Collects the elements of a sequence, tuple, or tuple variant.
+pub struct SeqSerializer {
+    ns: Vec<Node>,
+    variant: Option<&'static str>,
+}
+
+impl SeqSerializer {
+    fn end(self) -> Result<Node, Error> {
+        match self.variant {
+            Some(v) => {
+                let mut ns = self.ns;
+                ns.insert(0, name(v));
+                Ok(node(NodeKind::S(Rc::new(ns))))
+            }
+            None => Ok(node(NodeKind::Vector(Rc::new(self.ns)))),
+        }
+    }
+}
+
+// Collects the entries of a map, struct, or struct variant.
+pub struct MapSerializer {
+    ps: Vec<(Node, Node)>,
+    key: Option<Node>,
+    variant: Option<&'static str>,
+}
+
+impl MapSerializer {
+    fn end(self) -> Result<Node, Error> {
+        let map = node(NodeKind::Map(Rc::new(self.ps)));
+        match self.variant {
+            Some(v) => Ok(node(NodeKind::S(Rc::new(vec![name(v), map])))),
+            None => Ok(map),
+        }
+    }
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = Node;
+    type Error = Error;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Node, Error> {
+        Ok(node(NodeKind::LitBool(v)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Node, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Node, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Node, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Node, Error> {
+        Ok(node(NodeKind::LitNum(v)))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Node, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Node, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Node, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Node, Error> {
+        if v > i64::max_value() as u64 {
+            return Err(Error::new(format!("number out of range: {}", v)));
+        }
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Node, Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Node, Error> {
+        if !v.is_finite() {
+            return Err(Error::new(format!("number can't be written: {}", v)));
+        }
+        Ok(node(NodeKind::LitFloat(v)))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Node, Error> {
+        Ok(node(NodeKind::LitChar(v)))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Node, Error> {
+        Ok(node(NodeKind::LitStr(Str::new(v))))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Node, Error> {
+        Ok(node(NodeKind::Vector(Rc::new(v.iter().map(|&b| node(NodeKind::LitNum(b as i64))).collect()))))
+    }
+
+    fn serialize_none(self) -> Result<Node, Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Node, Error> {
+        let n = try!(value.serialize(self));
+        match n.kind {
+            NodeKind::S(ref ns) if ns.is_empty() => Err(Error::new("`Some` of a value written as `()` would read back as `None`")),
+            _ => Ok(n),
+        }
+    }
+
+    fn serialize_unit(self) -> Result<Node, Error> {
+        Ok(node(NodeKind::S(Rc::new(vec![]))))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Node, Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Node, Error> {
+        Ok(name(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Node, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self,
+                                                        _name: &'static str,
+                                                        _index: u32,
+                                                        variant: &'static str,
+                                                        value: &T)
+                                                        -> Result<Node, Error> {
+        Ok(node(NodeKind::S(Rc::new(vec![name(variant), try!(value.serialize(Serializer))]))))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer {
+            ns: Vec::with_capacity(len.unwrap_or(0)),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqSerializer, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self,
+                               _name: &'static str,
+                               _index: u32,
+                               variant: &'static str,
+                               len: usize)
+                               -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer {
+            ns: Vec::with_capacity(len),
+            variant: Some(variant),
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapSerializer, Error> {
+        Ok(MapSerializer {
+            ps: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<MapSerializer, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self,
+                                _name: &'static str,
+                                _index: u32,
+                                variant: &'static str,
+                                len: usize)
+                                -> Result<MapSerializer, Error> {
+        Ok(MapSerializer {
+            ps: Vec::with_capacity(len),
+            key: None,
+            variant: Some(variant),
+        })
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Node;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.ns.push(try!(value.serialize(Serializer)));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Node, Error> {
+        SeqSerializer::end(self)
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Node;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Node, Error> {
+        SeqSerializer::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Node;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Node, Error> {
+        SeqSerializer::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = Node;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Node, Error> {
+        SeqSerializer::end(self)
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Node;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(try!(key.serialize(Serializer)));
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.key.take().expect("serialize_value called before serialize_key");
+        self.ps.push((key, try!(value.serialize(Serializer))));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Node, Error> {
+        MapSerializer::end(self)
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Node;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.ps.push((name(key), try!(value.serialize(Serializer))));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Node, Error> {
+        MapSerializer::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+    type Ok = Node;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Node, Error> {
+        MapSerializer::end(self)
+    }
+}
+
+
+// Deserializing.
+
+pub struct Deserializer<'a> {
+    node: &'a Node,
+}
+
+impl<'a> Deserializer<'a> {
+    pub fn new(node: &'a Node) -> Deserializer<'a> {
+        Deserializer {
+            node: node,
+        }
+    }
+}
+
+// Visits ns as a sequence, all of the elements must be used.
+fn visit_seq<'de, V: Visitor<'de>>(ns: &[Node], visitor: V) -> Result<V::Value, Error> {
+    let mut seq = SeqAccess {
+        iter: ns.iter(),
+    };
+    let value = try!(visitor.visit_seq(&mut seq));
+    match seq.iter.next() {
+        Some(n) => Err(Error::new(format!("unexpected `{}`, expected {} elements", n, ns.len() - seq.iter.len() - 1)).at(n.span)),
+        None => Ok(value),
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Deserializer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let node = self.node;
+        let result = match node.kind {
+            NodeKind::LitNum(n) => visitor.visit_i64(n),
+            NodeKind::LitFloat(n) => visitor.visit_f64(n),
+            NodeKind::LitBool(b) => visitor.visit_bool(b),
+            NodeKind::LitChar(c) => visitor.visit_char(c),
+            NodeKind::LitStr(ref s) => visitor.visit_str(s),
+            NodeKind::Ident(s) => visitor.visit_str(&s),
+            NodeKind::Keyword(k) => visitor.visit_str(k),
+            NodeKind::S(ref ns) if ns.is_empty() => visitor.visit_unit(),
+            NodeKind::S(ref ns) | NodeKind::Vector(ref ns) => visit_seq(ns, visitor),
+            NodeKind::Map(ref ps) => {
+                visitor.visit_map(MapAccess {
+                    iter: ps.iter(),
+                    value: None,
+                })
+            }
+            NodeKind::Program(_) |
+            NodeKind::Quote(_) |
+            NodeKind::Quasiquote(_) |
+            NodeKind::Unquote(_) |
+            NodeKind::UnquoteSplicing(_) => Err(Error::new(format!("expected a value, found `{}`", node))),
+        };
+        result.map_err(|e| e.at(node.span))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let node = self.node;
+        match node.kind {
+            NodeKind::S(ref ns) if ns.is_empty() => visitor.visit_none().map_err(|e: Error| e.at(node.span)),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // `()` is unit for deserialize_any, but an empty list here.
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let node = self.node;
+        match node.kind {
+            NodeKind::S(ref ns) if ns.is_empty() => visit_seq(ns, visitor).map_err(|e| e.at(node.span)),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self,
+                                         _name: &'static str,
+                                         _variants: &'static [&'static str],
+                                         visitor: V)
+                                         -> Result<V::Value, Error> {
+        let node = self.node;
+        let result = match node.kind {
+            NodeKind::Ident(_) | NodeKind::Keyword(_) | NodeKind::LitStr(_) => {
+                visitor.visit_enum(EnumAccess {
+                    variant: node,
+                    args: &[],
+                })
+            }
+            NodeKind::S(ref ns) if !ns.is_empty() => {
+                visitor.visit_enum(EnumAccess {
+                    variant: &ns[0],
+                    args: &ns[1..],
+                })
+            }
+            _ => Err(Error::new(format!("expected a variant, `name` or `(name ...)`, found `{}`", node))),
+        };
+        result.map_err(|e| e.at(node.span))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf unit unit_struct tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct SeqAccess<'a> {
+    iter: slice::Iter<'a, Node>,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for SeqAccess<'a> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        match self.iter.next() {
+            Some(n) => seed.deserialize(Deserializer::new(n)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapAccess<'a> {
+    iter: slice::Iter<'a, (Node, Node)>,
+    // The value for the last key returned.
+    value: Option<&'a Node>,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for MapAccess<'a> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.iter.next() {
+            Some(&(ref k, ref v)) => {
+                self.value = Some(v);
+                seed.deserialize(Deserializer::new(k)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let v = self.value.take().expect("next_value_seed called before next_key_seed");
+        seed.deserialize(Deserializer::new(v))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+// `Variant` or `(Variant args...)`.
+struct EnumAccess<'a> {
+    variant: &'a Node,
+    args: &'a [Node],
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for EnumAccess<'a> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Error> {
+        let variant = try!(seed.deserialize(Deserializer::new(self.variant)));
+        Ok((variant, self))
+    }
+}
+
+impl<'a> EnumAccess<'a> {
+    fn expect_args(&self, n: usize) -> Result<(), Error> {
+        if self.args.len() == n {
+            return Ok(());
+        }
+        let msg = format!("expected {} argument{} for `{}`, found {}",
+                          n,
+                          if n == 1 { "" } else { "s" },
+                          self.variant,
+                          self.args.len());
+        Err(Error::new(msg))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for EnumAccess<'a> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        self.expect_args(0)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        try!(self.expect_args(1));
+        seed.deserialize(Deserializer::new(&self.args[0]))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        visit_seq(self.args, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        try!(self.expect_args(1));
+        de::Deserializer::deserialize_any(Deserializer::new(&self.args[0]), visitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Config {
+        name: String,
+        port: u16,
+        debug: bool,
+        ratio: f64,
+        sep: char,
+        tags: Vec<String>,
+        limits: BTreeMap<String, u32>,
+        mode: Mode,
+        modes: Vec<Mode>,
+        backup: Option<Backup>,
+        #[serde(rename = "let")]
+        keyword: (),
+        #[serde(rename = "two words")]
+        spaced: Id,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    #[serde(deny_unknown_fields)]
+    struct Backup {
+        path: String,
+        every: u32,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Id(u32);
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Mode {
+        Fast,
+        Slow(u32),
+        Range(i32, i32),
+        Custom { level: u8 },
+    }
+
+    fn config() -> Config {
+        let mut limits = BTreeMap::new();
+        limits.insert("cpu".to_owned(), 4);
+        limits.insert("memory".to_owned(), 1024);
+        Config {
+            name: "server \"one\"".to_owned(),
+            port: 8080,
+            debug: false,
+            ratio: 0.5,
+            sep: ',',
+            tags: vec!["a".to_owned(), "b".to_owned()],
+            limits: limits,
+            mode: Mode::Fast,
+            modes: vec![Mode::Slow(2), Mode::Range(-1, 1), Mode::Custom { level: 3 }],
+            backup: None,
+            keyword: (),
+            spaced: Id(7),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = to_string(&config()).unwrap();
+        assert!(text ==
+                "{name \"server \\\"one\\\"\"\n port 8080\n debug #f\n ratio 0.5\n sep #\\,\n tags [\"a\" \"b\"]\n \
+                 limits {\"cpu\" 4 \"memory\" 1024}\n mode Fast\n modes [(Slow 2) (Range -1 1) (Custom {level 3})]\n \
+                 backup ()\n let ()\n \"two words\" 7}",
+                "{}",
+                text);
+        assert!(from_str::<Config>(&text).unwrap() == config());
+
+        let mut c = config();
+        c.backup = Some(Backup {
+            path: "/tmp".to_owned(),
+            every: 60,
+        });
+        assert!(from_str::<Config>(&to_string(&c).unwrap()).unwrap() == c);
+    }
+
+    #[test]
+    fn test_read() {
+        // Comments, strings as keys, lists as sequences, and any order.
+        let text = "; the config\n{tags (\"x\") \"name\" \"s\" port 1 debug #t ratio 2 sep #\\space limits {} \
+                    mode (Custom {level 1}) modes [] backup {every 1 path \"p\"} let () \"two words\" 0}";
+        let c: Config = from_str(text).unwrap();
+        assert!(c.tags == vec!["x".to_owned()]);
+        assert!(c.ratio == 2.0);
+        assert!(c.mode == Mode::Custom { level: 1 });
+        assert!(c.backup.unwrap().every == 1);
+        assert!(from_str::<Vec<(i64, char)>>("[[1 #\\a] [2 #\\b]]").unwrap() == vec![(1, 'a'), (2, 'b')]);
+        assert!(from_str::<Vec<u32>>("(1 2)").unwrap() == vec![1, 2]);
+        assert!(from_str::<Vec<u32>>("()").unwrap().is_empty());
+        assert!(from_str::<Vec<Vec<u32>>>("(() [])").unwrap() == vec![Vec::<u32>::new(), vec![]]);
+    }
+
+    #[test]
+    fn test_errors() {
+        fn err<T: DeserializeOwned>(input: &str) -> String {
+            from_str::<T>(input).err().unwrap().to_string()
+        }
+        assert!(err::<Backup>("{path \"p\"\n every \"1\"}") == "2:8: invalid type: string \"1\", expected u32");
+        assert!(err::<Backup>("{path \"p\"}") == "1:1: missing field `every`");
+        assert!(err::<Backup>("{path \"p\" every 1 often #t}") == "1:19: unknown field `often`, expected `path` or `every`");
+        assert!(err::<Backup>("{path \"p\" every -1}") == "1:17: invalid value: integer `-1`, expected u32");
+        assert!(err::<Backup>("{path \"p\" every}") == "1:1: key without a value in map literal");
+        assert!(err::<Backup>("{path \"p\" every 1} {}") == "1:20: unexpected `{}` after the value");
+        assert!(err::<Backup>("{path \"\\q\"}") == "1:8: unknown escape: \\q");
+        assert!(err::<Backup>("") == "expected a value, found the end of the input");
+        assert!(err::<Mode>("Medium") == "1:1: unknown variant `Medium`, expected one of `Fast`, `Slow`, `Range`, `Custom`");
+        assert!(err::<Mode>("(Slow 1 2)") == "1:1: expected 1 argument for `Slow`, found 2");
+        assert!(err::<Mode>("(Range 1 2 3)") == "1:12: unexpected `3`, expected 2 elements");
+        assert!(err::<Mode>("'Fast") == "1:1: expected a variant, `name` or `(name ...)`, found `'Fast`");
+        assert!(err::<Vec<u8>>("[1 'a]") == "1:4: expected a value, found `'a`");
+        assert!(to_string(&::std::f64::NAN).is_err());
+        assert!(to_string(&::std::u64::MAX).is_err());
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Unit;
+
+    #[test]
+    fn test_option_of_unit() {
+        // Some of anything written as `()` would read back as None.
+        assert!(to_string(&Some(())).is_err());
+        assert!(to_string(&Some(Unit)).is_err());
+        assert!(to_string(&Some(None::<u32>)).is_err());
+        assert!(to_string(&vec![Some(())]).is_err());
+
+        assert!(from_str::<Option<()>>(&to_string(&None::<()>).unwrap()).unwrap() == None);
+        assert!(from_str::<Option<Unit>>(&to_string(&None::<Unit>).unwrap()).unwrap() == None);
+        assert!(from_str::<Option<Option<u32>>>(&to_string(&Some(Some(1))).unwrap()).unwrap() == Some(Some(1)));
+        assert!(from_str::<Option<Option<u32>>>(&to_string(&None::<Option<u32>>).unwrap()).unwrap() == None);
+    }
+}
@@ -0,0 +1,188 @@
+// Graphviz output.
+//
+// Renders ASTs as a DOT graph, e.g., `s dot | dot -Tsvg > ast.svg`. Lists
+// (s-expressions, vectors, maps, and quotes) are boxes labelled with their
+// kind, with an edge to each element; everything else is a leaf labelled with
+// its text. Each node's position in the source is shown under its label.
+//
+// Several trees (e.g., before and after expansion) can be drawn side by side,
+// each is a cluster in the same graph.
+
+use parser::{Node, NodeKind};
+use lexer::{Span, DUMMY_SP};
+use visit::{self, Visitor};
+use std::fmt::Write;
+
+// Where the nodes generated by macros come from, i.e., the spans of the bodies
+// of the macros defined in node (which should not have been expanded yet).
+//
+// Expansion keeps the spans of the nodes it copies, so a node in the expanded
+// tree whose span is inside one of these came from a macro body rather than
+// from the code which uses the macro.
+pub fn macro_bodies(node: &Node) -> Vec<Span> {
+    struct MacroBodies(Vec<Span>);
+
+    impl Visitor for MacroBodies {
+        fn visit_s(&mut self, ns: &[Node], _span: Span) {
+            if ns.len() > 2 && ns[0] == NodeKind::Keyword("macro") {
+                self.0.push(ns[ns.len() - 1].span);
+            }
+            visit::walk_nodes(self, ns)
+        }
+    }
+
+    let mut bodies = MacroBodies(Vec::new());
+    bodies.visit_node(node);
+    bodies.0
+}
+
+// trees is a list of (title, tree, highlight) triples, each tree is drawn as a
+// cluster. Nodes in a tree whose span is inside one of its highlight spans are
+// filled in.
+pub fn dot(trees: &[(&str, &Node, &[Span])]) -> String {
+    let mut graph = Graph {
+        out: String::new(),
+        next: 0,
+        highlight: &[],
+    };
+    graph.out.push_str("digraph ast {\n");
+    graph.out.push_str("    node [fontname=\"monospace\"];\n");
+    for (i, &(title, node, highlight)) in trees.iter().enumerate() {
+        let _ = write!(graph.out, "    subgraph cluster_{} {{\n        label=\"{}\";\n", i, escape(title));
+        graph.highlight = highlight;
+        graph.node(node);
+        graph.out.push_str("    }\n");
+    }
+    graph.out.push_str("}\n");
+    graph.out
+}
+
+struct Graph<'a> {
+    out: String,
+    // The id of the next node.
+    next: usize,
+    highlight: &'a [Span],
+}
+
+impl<'a> Graph<'a> {
+    // Writes node and its children, returns node's id.
+    fn node(&mut self, node: &Node) -> usize {
+        let id = self.next;
+        self.next += 1;
+
+        let (label, shape) = match node.kind {
+            NodeKind::Program(_) => ("Program".to_owned(), "box"),
+            NodeKind::S(_) => ("( )".to_owned(), "box"),
+            NodeKind::Vector(_) => ("[ ]".to_owned(), "box"),
+            NodeKind::Map(_) => ("{ }".to_owned(), "box"),
+            NodeKind::Quote(_) => ("'".to_owned(), "box"),
+            NodeKind::Quasiquote(_) => ("`".to_owned(), "box"),
+            NodeKind::Unquote(_) => (",".to_owned(), "box"),
+            NodeKind::UnquoteSplicing(_) => (",@".to_owned(), "box"),
+            NodeKind::Keyword(_) => (node.to_string(), "ellipse"),
+            _ => (node.to_string(), "plaintext"),
+        };
+        let mut label = escape(&label);
+        if node.span != DUMMY_SP {
+            let _ = write!(label, "\\n{}", node.span);
+        }
+        let _ = write!(self.out, "        n{} [label=\"{}\", shape={}", id, label, shape);
+        if self.highlight.iter().any(|s| s.contains(node.span)) {
+            self.out.push_str(", style=filled, fillcolor=\"#ffd27f\"");
+        }
+        self.out.push_str("];\n");
+
+        match node.kind {
+            NodeKind::Program(ref ns) | NodeKind::S(ref ns) | NodeKind::Vector(ref ns) => {
+                for n in ns.iter() {
+                    self.edge(id, n, None);
+                }
+            }
+            NodeKind::Map(ref ps) => {
+                for &(ref k, ref v) in ps.iter() {
+                    self.edge(id, k, Some("key"));
+                    self.edge(id, v, Some("value"));
+                }
+            }
+            NodeKind::Quote(ref n) |
+            NodeKind::Quasiquote(ref n) |
+            NodeKind::Unquote(ref n) |
+            NodeKind::UnquoteSplicing(ref n) => self.edge(id, n, None),
+            _ => {}
+        }
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: &Node, label: Option<&str>) {
+        let to = self.node(to);
+        let _ = write!(self.out, "        n{} -> n{}", from, to);
+        if let Some(label) = label {
+            let _ = write!(self.out, " [label=\"{}\"]", label);
+        }
+        self.out.push_str(";\n");
+    }
+}
+
+// s as the contents of a DOT string.
+fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '\n' => result.push_str("\\n"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parser;
+    use lexer;
+    use expand;
+
+    fn parse(input: &str) -> Node {
+        parser::parse(&lexer::lex(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_dot() {
+        let ast = parse("(print \"a\\\"b\" {k [1]})");
+        assert!(dot(&[("parsed", &ast, &[])]) ==
+                "digraph ast {\n    node [fontname=\"monospace\"];\n    subgraph cluster_0 {\n        \
+                 label=\"parsed\";\n        n0 [label=\"Program\\n1:1\", shape=box];\n        \
+                 n1 [label=\"( )\\n1:1\", shape=box];\n        n2 [label=\"print\\n1:2\", shape=ellipse];\n        \
+                 n1 -> n2;\n        n3 [label=\"\\\"a\\\\\\\"b\\\"\\n1:8\", shape=plaintext];\n        \
+                 n1 -> n3;\n        n4 [label=\"{ }\\n1:15\", shape=box];\n        \
+                 n5 [label=\"k\\n1:16\", shape=plaintext];\n        n4 -> n5 [label=\"key\"];\n        \
+                 n6 [label=\"[ ]\\n1:18\", shape=box];\n        n7 [label=\"1\\n1:19\", shape=plaintext];\n        \
+                 n6 -> n7;\n        n4 -> n6 [label=\"value\"];\n        n1 -> n4;\n        n0 -> n1;\n    \
+                 }\n}\n");
+        // No span for nodes which aren't from the source.
+        assert!(dot(&[("", &lit_num!(1), &[])]).contains("n0 [label=\"1\", shape=plaintext];"));
+    }
+
+    #[test]
+    fn test_macros() {
+        let ast = parse("(macro inc x `(+ ,x 1))\n(inc 41)");
+        let bodies = macro_bodies(&ast);
+        assert!(bodies == vec![Span::new(13, 22, 1, 14)]);
+
+        let expanded = expand::fold(ast.clone(), &mut expand::Unhygienic::new());
+        let graph = dot(&[("before", &ast, &[]), ("after", &expanded, &bodies)]);
+        assert!(graph.contains("subgraph cluster_0 {\n        label=\"before\";"));
+        assert!(graph.contains("subgraph cluster_1 {\n        label=\"after\";"));
+        // The `(+ ...)` and `1` come from the macro, `41` from the call.
+        assert!(graph.contains("[label=\"( )\\n1:15\", shape=box, style=filled"));
+        assert!(graph.contains("[label=\"1\\n1:21\", shape=plaintext, style=filled"));
+        assert!(graph.contains("[label=\"41\\n2:6\", shape=plaintext];"));
+        // Only the expanded tree is highlighted.
+        let before = &graph[..graph.find("subgraph cluster_1").unwrap()];
+        assert!(!before.contains("style=filled"));
+    }
+}
@@ -1,55 +1,118 @@
-use parser::Node;
-use lexer::Str;
+use parser::{Node, NodeKind};
+use lexer::Span;
+use symbol::Symbol;
+use forms;
+use visit::{self, VisitorMut};
+use interpreter::{self, Envr};
 use std::collections::HashMap;
+use std::rc::Rc;
 
 pub fn fold(node: Node, fld: &mut Folder) -> Node {
-    match node {
-        Node::Program(ns) => Node::Program(ns.into_iter().map(|n| fold(n, fld)).collect()),
-        Node::S(ns) => {
-            match &ns[0] {
-                &Node::Macro => {
-                    fld.fold_macro(ns)
-                }
-                &Node::Ident(_) => {
-                    fld.fold_ident(ns)
-                }
-                _ => {
-                    Node::S(ns.into_iter().map(|n| fold(n, fld)).collect())
+    let mut node = node;
+    Expander {
+        fld: fld,
+    }.visit_node(&mut node);
+    node
+}
+
+// Expands `(keyword ...)` using the special form and `(id ...)` using the
+// folder, everything else is walked.
+struct Expander<'a> {
+    fld: &'a mut Folder,
+}
+
+impl<'a> VisitorMut for Expander<'a> {
+    fn visit_node(&mut self, node: &mut Node) {
+        let span = node.span;
+        let expanded = match node.kind {
+            NodeKind::S(ref ns) => {
+                match ns.first().map(|n| &n.kind) {
+                    Some(&NodeKind::Keyword(k)) => forms::lookup(k).map(|form| form.expand(ns.to_vec(), span, self.fld)),
+                    Some(&NodeKind::Ident(_)) => Some(self.fld.fold_ident(ns.to_vec(), span)),
+                    _ => None,
                 }
             }
+            _ => None,
+        };
+        match expanded {
+            Some(n) => *node = n,
+            None => visit::walk_node_mut(self, node),
+        }
+    }
+
+    // Quoted data is not code, so there is nothing to expand.
+    fn visit_quote(&mut self, _n: &mut Rc<Node>, _span: Span) {}
+
+    fn visit_quasiquote(&mut self, n: &mut Rc<Node>, _span: Span) {
+        let mut template = Template {
+            fld: &mut *self.fld,
+            depth: 0,
+        };
+        visit::walk_rc_mut(&mut template, n)
+    }
+}
+
+// Only the unquoted parts of a quasiquote template are code, so only those
+// are expanded. depth is the number of quasiquotes enclosing the node being
+// visited, not counting the outermost one.
+struct Template<'a> {
+    fld: &'a mut Folder,
+    depth: usize,
+}
+
+impl<'a> Template<'a> {
+    // The body of an unquote in the template.
+    fn unquoted(&mut self, n: &mut Rc<Node>) {
+        if self.depth == 0 {
+            let mut expander = Expander {
+                fld: &mut *self.fld,
+            };
+            visit::walk_rc_mut(&mut expander, n);
+        } else {
+            self.depth -= 1;
+            visit::walk_rc_mut(self, n);
+            self.depth += 1;
         }
-        Node::Ident(_) |
-        Node::Plus |
-        Node::Fn |
-        Node::Let |
-        Node::Print |
-        Node::LitNum(_) |
-        Node::LitStr(_) => node,
-        Node::Macro => panic!("noop fold of macro"),
+    }
+}
+
+impl<'a> VisitorMut for Template<'a> {
+    fn visit_quasiquote(&mut self, n: &mut Rc<Node>, _span: Span) {
+        self.depth += 1;
+        visit::walk_rc_mut(self, n);
+        self.depth -= 1;
+    }
+
+    fn visit_unquote(&mut self, n: &mut Rc<Node>, _span: Span) {
+        self.unquoted(n)
+    }
+
+    fn visit_unquote_splicing(&mut self, n: &mut Rc<Node>, _span: Span) {
+        self.unquoted(n)
     }
 }
 
 pub trait Folder {
-    // Fold (id ...) ns includes id
-    fn fold_ident(&mut self, ns: Vec<Node>) -> Node;
-    // Fold (macro ...) ns includes macro
-    fn fold_macro(&mut self, ns: Vec<Node>) -> Node;
+    // Fold (id ...) ns includes id, span is the span of the whole s-expr.
+    fn fold_ident(&mut self, ns: Vec<Node>, span: Span) -> Node;
+    // Fold (macro ...) ns includes macro, span is the span of the whole s-expr.
+    fn fold_macro(&mut self, ns: Vec<Node>, span: Span) -> Node;
 }
 
 pub struct NoopFolder;
 
 impl Folder for NoopFolder {
-    fn fold_ident(&mut self, ns: Vec<Node>) -> Node {
-        Node::S(ns.into_iter().map(|n| fold(n, self)).collect())
+    fn fold_ident(&mut self, ns: Vec<Node>, span: Span) -> Node {
+        Node::new(NodeKind::S(Rc::new(ns.into_iter().map(|n| fold(n, self)).collect())), span)
     }    
 
-    fn fold_macro(&mut self, ns: Vec<Node>) -> Node {
-        Node::S(ns.into_iter().map(|n| fold(n, self)).collect())
+    fn fold_macro(&mut self, ns: Vec<Node>, span: Span) -> Node {
+        Node::new(NodeKind::S(Rc::new(ns.into_iter().map(|n| fold(n, self)).collect())), span)
     }    
 }
 
 pub struct Unhygienic {
-    macros: HashMap<Str, (Vec<Str>, Node)>,
+    macros: HashMap<Symbol, (Vec<Symbol>, Node)>,
 }
 
 impl Unhygienic {
@@ -61,51 +124,97 @@ impl Unhygienic {
 }
 
 impl Folder for Unhygienic {
-    fn fold_ident(&mut self, ns: Vec<Node>) -> Node {
+    fn fold_ident(&mut self, ns: Vec<Node>, span: Span) -> Node {
         {
             let name = ns[0].expect_ident();
-            if self.macros.contains_key(name) {
-                let &(ref args, ref body) = &self.macros[name];
-                assert!(ns.len() - 1 == args.len());
+            if self.macros.contains_key(&name) {
+                let &(ref args, ref body) = &self.macros[&name];
+                assert!(ns.len() - 1 == args.len(),
+                        "Mismatch in number of macro arguments at {}. Expected: {}, found: {}",
+                        span,
+                        args.len(),
+                        ns.len() - 1);
+                if let NodeKind::Quasiquote(_) = body.kind {
+                    // The body is a template, evaluating it with the
+                    // arguments (unevaluated) bound to the formals gives the
+                    // expansion.
+                    let envr = Envr::new();
+                    let _guard = envr.push_rib();
+                    for (a, n) in args.iter().zip(ns[1..].iter()) {
+                        envr.store(*a, n.clone());
+                    }
+                    return interpreter::run_node(body, &envr);
+                }
                 return body.subst(args, &ns[1..]);
             }
         }
-        Node::S(ns)
+        Node::new(NodeKind::S(Rc::new(ns)), span)
     }    
 
-    fn fold_macro(&mut self, ns: Vec<Node>) -> Node {
+    fn fold_macro(&mut self, ns: Vec<Node>, span: Span) -> Node {
         let mut ns = ns;
-        let name = ns[1].expect_ident().clone();
+        let name = ns[1].expect_ident();
         let body = ns.pop().unwrap();
         // FIXME some kind of split would be more efficient.
-        let args = ns[2..].iter().map(|n| n.expect_ident().clone()).collect();
+        let args = ns[2..].iter().map(|n| n.expect_ident()).collect();
         self.macros.insert(name, (args, body));
-        s!()
+        Node::new(NodeKind::S(Rc::new(vec![])), span)
     }    
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use parser::Node;
 
     #[test]
     fn test_noop() {
         let noop = &mut NoopFolder;
         let p = program!();
         assert!(p.clone() == fold(p, noop));
-        let p = program!(s!(Node::Print, lit_str!("Hello world!")));
+        let p = program!(s!(kw!("print"), lit_str!("Hello world!")));
         assert!(p.clone() == fold(p, noop));
         let p = program!(ident!("a"),
-                         s!(Node::Let,
+                         s!(kw!("let"),
                             ident!("a"),
                             lit_num!(42),
-                            s!(Node::Fn,
+                            s!(kw!("fn"),
                                ident!("x"),
-                               s!(Node::Plus, ident!("x"), ident!("a")))));
+                               s!(kw!("+"), ident!("x"), ident!("a")))));
         assert!(p.clone() == fold(p, noop));
-        let p = program!(s!(s!(Node::Fn, ident!("x"), s!(Node::Plus, ident!("x"), lit_num!(42))),
-                            s!(Node::Plus, lit_num!(3), lit_str!("a string"))));
+        let p = program!(s!(s!(kw!("fn"), ident!("x"), s!(kw!("+"), ident!("x"), lit_num!(42))),
+                            s!(kw!("+"), lit_num!(3), lit_str!("a string"))));
         assert!(p.clone() == fold(p, noop));
     }
+
+    #[test]
+    fn test_quotes() {
+        let mut fld = Unhygienic::new();
+        let p = program!(s!(kw!("macro"), ident!("m"), ident!("x"), ident!("x")),
+                         quote!(s!(ident!("m"), lit_num!(1))),
+                         quasiquote!(s!(ident!("m"), unquote!(s!(ident!("m"), lit_num!(2))),
+                                        quasiquote!(unquote!(s!(ident!("m"), lit_num!(3)))))));
+        // Only the unquote at depth 0 is expanded.
+        assert!(fold(p, &mut fld) ==
+                program!(s!(),
+                         quote!(s!(ident!("m"), lit_num!(1))),
+                         quasiquote!(s!(ident!("m"), unquote!(lit_num!(2)),
+                                        quasiquote!(unquote!(s!(ident!("m"), lit_num!(3))))))));
+    }
+
+    #[test]
+    fn test_template_macro() {
+        let mut fld = Unhygienic::new();
+        // (macro inc x `(+ ,x 1)) (inc (+ 2 3))
+        let p = program!(s!(kw!("macro"), ident!("inc"), ident!("x"),
+                            quasiquote!(s!(kw!("+"), unquote!(ident!("x")), lit_num!(1)))),
+                         s!(ident!("inc"), s!(kw!("+"), lit_num!(2), lit_num!(3))));
+        assert!(fold(p, &mut fld) ==
+                program!(s!(), s!(kw!("+"), s!(kw!("+"), lit_num!(2), lit_num!(3)), lit_num!(1))));
+        // (macro do xs `(+ ,@xs)) (do (1 2 3))
+        let p = program!(s!(kw!("macro"), ident!("do"), ident!("xs"),
+                            quasiquote!(s!(kw!("+"), unquote_splicing!(ident!("xs"))))),
+                         s!(ident!("do"), s!(lit_num!(1), lit_num!(2), lit_num!(3))));
+        assert!(fold(p, &mut fld) ==
+                program!(s!(), s!(kw!("+"), lit_num!(1), lit_num!(2), lit_num!(3))));
+    }
 }
@@ -0,0 +1,350 @@
+// Special forms.
+//
+// Every keyword in the language is a special form registered here. The lexer
+// uses the registry to decide which names are keywords, the parser turns them
+// into NodeKind::Keyword nodes, and the expander and interpreter delegate to
+// the form to expand or evaluate `(keyword ...)`.
+//
+// Embedders can add their own forms (or replace the built-in ones) using
+// `register`. The registry is per-thread, forms should be registered before
+// lexing any input which uses them.
+
+use parser::{Node, NodeKind};
+use lexer::Span;
+use expand::{self, Folder};
+use interpreter::{self, Envr};
+use check::{self, CheckError, CheckErrorKind};
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+use std::collections::HashMap;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub trait SpecialForm {
+    // The keyword which introduces the form, e.g., "let".
+    fn name(&self) -> &'static str;
+
+    // If true, `(keyword ...)` is a value, i.e., it evaluates to itself and
+    // may be applied to arguments (see apply).
+    fn is_value(&self) -> bool {
+        false
+    }
+
+    // Expand `(keyword ...)`, ns includes the keyword and span is the span of
+    // the whole s-expression. By default each element is folded.
+    fn expand(&self, ns: Vec<Node>, span: Span, fld: &mut Folder) -> Node {
+        Node::new(NodeKind::S(Rc::new(ns.into_iter().map(|n| expand::fold(n, fld)).collect())), span)
+    }
+
+    // Evaluate input, which is `(keyword ...)`.
+    fn eval(&self, input: &Node, envr: &Envr) -> Node;
+
+    // Evaluate input, which is `((keyword ...) ...)`. f is `(keyword ...)`
+    // and args are the evaluated arguments. Only called if is_value is true.
+    fn apply(&self, f: &Node, _args: Vec<Node>, input: &Node, _envr: &Envr) -> Node {
+        panic!("Can't apply `{}` at {}: {}", self.name(), input.span, f)
+    }
+
+    // How the pretty printer breaks `(keyword ...)` over lines.
+    fn layout(&self) -> Layout {
+        Layout::Call
+    }
+
+    // Check the shape of `(keyword ...)` before it is expanded or run. ns
+    // includes the keyword and span is the span of the whole s-expression.
+    // The elements are checked separately, see check.rs.
+    fn check(&self, _ns: &[Node], _span: Span, _errors: &mut Vec<CheckError>) {}
+}
+
+// Layouts for a special form which doesn't fit on one line, see pretty.rs.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Layout {
+    // Like a function call, the arguments are aligned with the first one.
+    Call,
+    // The last element is a body, it goes on its own line and is indented.
+    // The other elements stay on the first line if they can.
+    Body,
+    // `(keyword name value ... body)`, each name and value pair goes on its
+    // own line, aligned with the first, then the body is indented.
+    Bindings,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<HashMap<&'static str, Rc<SpecialForm>>> = RefCell::new(builtins());
+}
+
+fn builtins() -> HashMap<&'static str, Rc<SpecialForm>> {
+    let forms: Vec<Rc<SpecialForm>> = vec![Rc::new(Plus), Rc::new(Fn), Rc::new(Let), Rc::new(Macro), Rc::new(Print)];
+    forms.into_iter().map(|f| (f.name(), f)).collect()
+}
+
+// Adds a form to the registry, replacing any existing form with the same name.
+pub fn register(form: Rc<SpecialForm>) {
+    REGISTRY.with(|r| r.borrow_mut().insert(form.name(), form));
+}
+
+pub fn lookup(name: &str) -> Option<Rc<SpecialForm>> {
+    REGISTRY.with(|r| r.borrow().get(name).cloned())
+}
+
+// The name of a form. An alias, because serde assumes a field of type `&str`
+// is borrowed from the input, but keywords are deserialized by
+// deserialize_keyword.
+pub type Keyword = &'static str;
+
+// For deserializing keywords (in tokens and nodes), which must be the name of
+// a registered form.
+pub fn deserialize_keyword<'de, D: Deserializer<'de>>(d: D) -> Result<Keyword, D::Error> {
+    let name = try!(String::deserialize(d));
+    match lookup(&name) {
+        Some(form) => Ok(form.name()),
+        None => Err(D::Error::custom(format!("unknown keyword: {}", name))),
+    }
+}
+
+// The form for node, if node is `(keyword ...)`.
+pub fn head(node: &Node) -> Option<Rc<SpecialForm>> {
+    match node.kind {
+        NodeKind::S(ref ns) if ns.len() > 0 => match ns[0].kind {
+            NodeKind::Keyword(k) => lookup(k),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+
+// Built-in forms.
+
+// (+ n ...)
+struct Plus;
+
+impl SpecialForm for Plus {
+    fn name(&self) -> &'static str {
+        "+"
+    }
+
+    fn eval(&self, input: &Node, envr: &Envr) -> Node {
+        let args = interpreter::run_args(input, envr);
+        let result = args.iter().fold(NodeKind::LitNum(0), |a, n| plus(a, n, input));
+        Node::new(result, input.span)
+    }
+}
+
+// Integers are added exactly, if either side is a float then the result is a
+// float.
+fn plus(a: NodeKind, b: &Node, input: &Node) -> NodeKind {
+    match (a, &b.kind) {
+        (NodeKind::LitNum(a), &NodeKind::LitNum(b)) => {
+            match a.checked_add(b) {
+                Some(n) => NodeKind::LitNum(n),
+                None => panic!("Integer overflow in `+` at {}", input.span),
+            }
+        }
+        (NodeKind::LitNum(a), &NodeKind::LitFloat(b)) => NodeKind::LitFloat(a as f64 + b),
+        (NodeKind::LitFloat(a), &NodeKind::LitNum(b)) => NodeKind::LitFloat(a + b as f64),
+        (NodeKind::LitFloat(a), &NodeKind::LitFloat(b)) => NodeKind::LitFloat(a + b),
+        _ => panic!("expected a number, found {} at {}", b, b.span),
+    }
+}
+
+// (fn formals... body)
+struct Fn;
+
+impl SpecialForm for Fn {
+    fn name(&self) -> &'static str {
+        "fn"
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::Body
+    }
+
+    fn check(&self, ns: &[Node], span: Span, errors: &mut Vec<CheckError>) {
+        if ns.len() < 2 {
+            return errors.push(CheckError::new(CheckErrorKind::MissingBody("fn"), span));
+        }
+        for n in &ns[1..ns.len() - 1] {
+            check::expect_name("fn", n, errors);
+        }
+    }
+
+    fn is_value(&self) -> bool {
+        true
+    }
+
+    fn eval(&self, input: &Node, _envr: &Envr) -> Node {
+        input.clone()
+    }
+
+    fn apply(&self, f: &Node, args: Vec<Node>, input: &Node, envr: &Envr) -> Node {
+        let sub_ns = match f.kind {
+            NodeKind::S(ref ns) => ns,
+            _ => unreachable!(),
+        };
+        assert!(sub_ns.len() > 1, "No body for function at {}: {}", f.span, f);
+        let len = sub_ns.len();
+        let fun_body = &sub_ns[len - 1];
+        let formals: Vec<_> = sub_ns[1..len - 1].iter().map(|n| n.expect_ident()).collect();
+        assert!(args.len() == formals.len(),
+                "Mismatch in number of function arguments at {}. Expected: {}, found: {}",
+                input.span,
+                formals.len(),
+                args.len());
+
+        let _guard = envr.push_rib();
+        for (formal, actual) in formals.into_iter().zip(args.into_iter()) {
+            envr.store(formal, actual);
+        }
+        interpreter::run_node(fun_body, envr)
+    }
+}
+
+// (let name value ... body)
+struct Let;
+
+impl SpecialForm for Let {
+    fn name(&self) -> &'static str {
+        "let"
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::Bindings
+    }
+
+    fn check(&self, ns: &[Node], span: Span, errors: &mut Vec<CheckError>) {
+        if ns.len() < 2 {
+            return errors.push(CheckError::new(CheckErrorKind::MissingBody("let"), span));
+        }
+        let bindings = &ns[1..ns.len() - 1];
+        for b in bindings.chunks(2) {
+            check::expect_name("let", &b[0], errors);
+            if b.len() == 1 {
+                errors.push(CheckError::new(CheckErrorKind::BindingWithoutValue("let"), b[0].span));
+            }
+        }
+    }
+
+    fn eval(&self, input: &Node, envr: &Envr) -> Node {
+        let ns = match input.kind {
+            NodeKind::S(ref ns) => ns,
+            _ => unreachable!(),
+        };
+        let len = ns.len();
+        let body = &ns[len - 1];
+        let args = &ns[1..len - 1];
+        assert!(args.len() % 2 == 0, "Argument without a value in `let` at {}", input.span);
+        let _guard = envr.push_rib();
+        for i in 0..args.len() / 2 {
+            let arg_name = args[i * 2].expect_ident();
+            let arg_value = interpreter::run_node(&args[i * 2 + 1], envr);
+            envr.store(arg_name, arg_value);
+        }
+        interpreter::run_node(body, envr)
+    }
+}
+
+// (macro name formals... body)
+struct Macro;
+
+impl SpecialForm for Macro {
+    fn name(&self) -> &'static str {
+        "macro"
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::Body
+    }
+
+    fn check(&self, ns: &[Node], span: Span, errors: &mut Vec<CheckError>) {
+        match ns.len() {
+            1 => return errors.push(CheckError::new(CheckErrorKind::MissingName("macro"), span)),
+            2 => return errors.push(CheckError::new(CheckErrorKind::MissingBody("macro"), span)),
+            _ => {}
+        }
+        for n in &ns[1..ns.len() - 1] {
+            check::expect_name("macro", n, errors);
+        }
+    }
+
+    fn expand(&self, ns: Vec<Node>, span: Span, fld: &mut Folder) -> Node {
+        fld.fold_macro(ns, span)
+    }
+
+    fn eval(&self, input: &Node, _envr: &Envr) -> Node {
+        panic!("Unexpanded macro at {}: {}", input.span, input)
+    }
+}
+
+// (print value ...)
+struct Print;
+
+impl SpecialForm for Print {
+    fn name(&self) -> &'static str {
+        "print"
+    }
+
+    fn eval(&self, input: &Node, envr: &Envr) -> Node {
+        let args = interpreter::run_args(input, envr);
+        for a in &args {
+            // Strings and chars are printed without quotes or escapes.
+            match a.kind {
+                NodeKind::LitStr(ref s) => println!("{}", s),
+                NodeKind::LitChar(c) => println!("{}", c),
+                _ => println!("{}", a),
+            }
+        }
+        Node::new(NodeKind::S(Rc::new(vec![])), input.span)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lexer::{self, TokenKind};
+    use parser::{self, NodeKind};
+    use interpreter::{self, Envr};
+    use expand;
+    use std::rc::Rc;
+
+    // (seq e ...) evaluates each e and returns the last.
+    struct Seq;
+
+    impl SpecialForm for Seq {
+        fn name(&self) -> &'static str {
+            "seq"
+        }
+
+        fn eval(&self, input: &Node, envr: &Envr) -> Node {
+            let mut args = interpreter::run_args(input, envr);
+            args.pop().unwrap_or(Node::new(NodeKind::S(Rc::new(vec![])), input.span))
+        }
+    }
+
+    #[test]
+    fn test_builtins() {
+        for name in &["+", "fn", "let", "macro", "print"] {
+            assert!(lookup(name).unwrap().name() == *name);
+        }
+        assert!(lookup("seq").is_none());
+        assert!(lookup("fn").unwrap().is_value());
+        assert!(!lookup("let").unwrap().is_value());
+    }
+
+    #[test]
+    fn test_register() {
+        assert!(lexer::lex("seq").unwrap() == vec![TokenKind::Name(::symbol::Symbol::intern("seq"))]);
+        register(Rc::new(Seq));
+        assert!(lexer::lex("seq").unwrap() == vec![TokenKind::Keyword("seq")]);
+
+        let ast = parser::parse(&lexer::lex("(seq 1 (let x 2 (+ x x)))").unwrap()).unwrap();
+        assert!(ast == program!(s!(kw!("seq"), lit_num!(1),
+                                   s!(kw!("let"), ident!("x"), lit_num!(2), s!(kw!("+"), ident!("x"), ident!("x"))))));
+        assert!(ast.to_string() == "(seq 1 (let x 2 (+ x x)))");
+        assert!(head(&s!(kw!("seq"))).unwrap().name() == "seq");
+        assert!(head(&s!(ident!("seq"))).is_none());
+
+        let ast = expand::fold(ast, &mut expand::Unhygienic::new());
+        assert!(interpreter::run_program(&ast) == vec![lit_num!(4)]);
+    }
+}
@@ -1,15 +1,19 @@
-use parser::Node;
-use lexer::Str;
+use parser::{Node, NodeKind, unshare};
+use symbol::Symbol;
+use forms;
+use lexer::Span;
+use visit::{self, VisitorMut};
 use std::collections::HashMap;
 use std::cell::RefCell;
+use std::rc::Rc;
 
-type Rib = HashMap<Str, Node>;
+type Rib = HashMap<Symbol, Node>;
 
-struct Envr {
+pub struct Envr {
     ribs: RefCell<Vec<Rib>>,
 }
 
-struct RibGuard<'a> {
+pub struct RibGuard<'a> {
     envr: &'a Envr,
 }
 
@@ -20,7 +24,7 @@ impl<'a> Drop for RibGuard<'a> {
 }
 
 impl Envr {
-    fn new() -> Envr {
+    pub fn new() -> Envr {
         Envr {
             ribs: RefCell::new(Vec::new()),
         }
@@ -28,31 +32,32 @@ impl Envr {
 
     fn with_value(name: &str, value: Node) -> Envr {
         let mut map = HashMap::new();
-        map.insert(Str::new(name), value);
+        map.insert(Symbol::intern(name), value);
         Envr {
             ribs: RefCell::new(vec![map]),
         }
     }
 
-    fn push_rib(&self) -> RibGuard {
+    // The rib is popped when the guard is dropped.
+    pub fn push_rib(&self) -> RibGuard {
         self.ribs.borrow_mut().push(HashMap::new());
         RibGuard {
             envr: self
         }
     }
 
-    fn store(&self, name: &Str, value: Node) {
+    pub fn store(&self, name: Symbol, value: Node) {
         let mut ribs = self.ribs.borrow_mut();
         assert!(ribs.len() > 0, "No ribs in environment");
         let len = ribs.len();
         let rib = &mut ribs[len - 1];
-        assert!(!rib.contains_key(name), "Identifier already exists in rib: {}", name);
-        rib.insert(name.clone(), value);
+        assert!(!rib.contains_key(&name), "Identifier already exists in rib: {}", name);
+        rib.insert(name, value);
     }
 
-    fn lookup(&self, name: &Str) -> Option<Node> {
+    pub fn lookup(&self, name: Symbol) -> Option<Node> {
         for rib in self.ribs.borrow().iter().rev() {
-            if let Some(value) = rib.get(name) {
+            if let Some(value) = rib.get(&name) {
                 return Some(value.clone());
             }
         }
@@ -64,8 +69,8 @@ impl Envr {
 pub fn run_program(input: &Node) -> Vec<Node> {
     let mut result = Vec::new();
 
-    if let &Node::Program(ref ns) = input {
-        for n in ns {
+    if let NodeKind::Program(ref ns) = input.kind {
+        for n in ns.iter() {
             let mut envr = Envr::new();
             result.push(run_node(n, &mut envr));
         }
@@ -76,86 +81,160 @@ pub fn run_program(input: &Node) -> Vec<Node> {
     result
 }
 
-fn run_node(input: &Node, envr: &Envr) -> Node {
-    match *input {
-        ref t if t.is_value() => t.clone(),
-        Node::S(ref ns) => {
-            match ns[0] {
-                Node::Print => {
-                    let args = run_args(input, envr);
-                    for a in &args {
-                        println!("{}", a);
-                    }
-                    s!()
-                }
-                Node::Plus => {
-                    let args = run_args(input, envr);
-                    let result = args.iter().fold(0, |a, n| a + n.expect_lit_num());
-                    lit_num!(result)
-                }
-                Node::Let => {
-                    let len = ns.len();
-                    let body = &ns[len - 1];
-                    let args = &ns[1..len - 1];
-                    assert!(args.len() % 2 == 0, "Argument without a value in `let`");
-                    let _guard = envr.push_rib();
-                    for i in 0..args.len() / 2 {
-                        let arg_name = &args[i * 2].expect_ident();
-                        let arg_value = run_node(&args[i * 2 + 1], envr);
-                        envr.store(arg_name, arg_value);
-                    }
-                    run_node(body, envr)
-                }
-                Node::S(ref sub_ns) if sub_ns.len() > 0 && sub_ns[0] == Node::Fn => {
-                    assert!(sub_ns.len() > 1, "No body for function: {:?}", &ns[0]);
-                    let len = sub_ns.len();
-                    let fun_body = &sub_ns[len - 1];
-                    let formals: Vec<_> = sub_ns[1..len - 1].iter().map(|n| n.expect_ident()).collect();
+pub fn run_node(input: &Node, envr: &Envr) -> Node {
+    match input.kind {
+        _ if input.is_value() => input.clone(),
+        NodeKind::S(ref ns) => {
+            if let Some(form) = forms::head(input) {
+                return form.eval(input, envr);
+            }
+            if let Some(form) = forms::head(&ns[0]) {
+                if form.is_value() {
                     let args = run_args(input, envr);
-                    assert!(args.len() == formals.len(),
-                            "Mismatch in number of function arguments. Expected: {}, found: {}",
-                            formals.len(),
-                            args.len());
-
-                    let _guard = envr.push_rib();
-                    for (ref formal, actual) in formals.iter().zip(args.iter()) {
-                        envr.store(formal, actual.clone());
-                    }
-                    run_node(fun_body, envr)
-                }
-                ref n => {
-                    // Some pretty crufty logic down here.
-                    // We are inside an s-expr and inspecting the first element.
-                    let mut reduced_els = match run_node(n, envr) {
-                        // First element is an empty s-expr, forget it:
-                        // (() ...) --> (...)
-                        Node::S(ref ns) if ns.len() == 0 => Vec::new(),
-                        // First and only element is a value, promote it to the value.
-                        // (v) --> v
-                        ref r if ns.len() == 1 && r.is_value() => return r.clone(),
-                        // Otherwise just reduce the first element.
-                        // n0 --> n0'
-                        // ---------------------
-                        // (n0 ...) -> (n0' ...)
-                        r => vec!(r),
-                    };
-                    reduced_els.extend(ns[1..].iter().map(|n| n.clone()));
-                    run_node(&Node::S(reduced_els), envr)
+                    return form.apply(&ns[0], args, input, envr);
                 }
             }
+
+            let n = &ns[0];
+            // Some pretty crufty logic down here.
+            // We are inside an s-expr and inspecting the first element.
+            let r = run_node(n, envr);
+            let mut reduced_els = match r.kind {
+                // First element is an empty s-expr, forget it:
+                // (() ...) --> (...)
+                NodeKind::S(ref ns) if ns.len() == 0 => Vec::new(),
+                // First and only element is a value, promote it to the value.
+                // (v) --> v
+                _ if ns.len() == 1 && r.is_value() => return r.clone(),
+                // Otherwise just reduce the first element.
+                // n0 --> n0'
+                // ---------------------
+                // (n0 ...) -> (n0' ...)
+                _ => vec!(r.clone()),
+            };
+            reduced_els.extend(ns[1..].iter().map(|n| n.clone()));
+            run_node(&Node::new(NodeKind::S(Rc::new(reduced_els)), input.span), envr)
         }
-        Node::Ident(ref s) => {
+        // A collection which isn't a value yet, so evaluate its elements.
+        NodeKind::Vector(ref ns) => {
+            Node::new(NodeKind::Vector(Rc::new(ns.iter().map(|n| run_node(n, envr)).collect())), input.span)
+        }
+        NodeKind::Map(ref ps) => {
+            let ps = ps.iter().map(|&(ref k, ref v)| (run_node(k, envr), run_node(v, envr))).collect();
+            Node::new(NodeKind::Map(Rc::new(ps)), input.span)
+        }
+        // Quoted data evaluates to itself.
+        NodeKind::Quote(ref n) => (**n).clone(),
+        NodeKind::Quasiquote(ref n) => run_template(n, envr),
+        NodeKind::Unquote(_) | NodeKind::UnquoteSplicing(_) => {
+            panic!("Unquote outside of a quasiquote at {}: {}", input.span, input)
+        }
+        NodeKind::Ident(s) => {
             if let Some(n) = envr.lookup(s) {
                 return n;
             }
-            panic!("Unknown identifier: {}", s);
+            panic!("Unknown identifier at {}: {}", input.span, s);
         }
-        _ => panic!("Unexpected node: {:?}", input),
+        _ => panic!("Unexpected node at {}: {}", input.span, input),
+    }
+}
+
+// Evaluates a quasiquote template into data, unquotes are replaced by their
+// values.
+fn run_template(input: &Node, envr: &Envr) -> Node {
+    let mut result = input.clone();
+    Template {
+        envr: envr,
+        depth: 0,
+    }.visit_node(&mut result);
+    result
+}
+
+// depth is the number of quasiquotes enclosing the node being visited, not
+// counting the outermost one; only unquotes at depth 0 are evaluated.
+struct Template<'a> {
+    envr: &'a Envr,
+    depth: usize,
+}
+
+impl<'a> Template<'a> {
+    // The elements of a list or vector in the template, unquote-splicings at
+    // depth 0 are spliced in.
+    fn splice(&mut self, ns: &mut Rc<Vec<Node>>) {
+        let splices = ns.iter().any(|n| match n.kind {
+            NodeKind::UnquoteSplicing(_) => true,
+            _ => false,
+        });
+        if self.depth > 0 || !splices {
+            return visit::walk_nodes_mut(self, ns);
+        }
+
+        let mut result = Vec::new();
+        for n in ns.iter() {
+            match n.kind {
+                NodeKind::UnquoteSplicing(ref spliced) => {
+                    let spliced = run_node(spliced, self.envr);
+                    match spliced.kind {
+                        NodeKind::S(ns) | NodeKind::Vector(ns) => result.extend(unshare(ns)),
+                        _ => panic!("`,@` expected a list, found {} at {}", spliced, n.span),
+                    }
+                }
+                _ => {
+                    let mut n = n.clone();
+                    self.visit_node(&mut n);
+                    result.push(n);
+                }
+            }
+        }
+        *ns = Rc::new(result);
+    }
+
+    fn nested(&mut self, n: &mut Rc<Node>) {
+        self.depth -= 1;
+        visit::walk_rc_mut(self, n);
+        self.depth += 1;
+    }
+}
+
+impl<'a> VisitorMut for Template<'a> {
+    fn visit_node(&mut self, node: &mut Node) {
+        let value = match node.kind {
+            NodeKind::Unquote(ref n) if self.depth == 0 => run_node(n, self.envr),
+            NodeKind::UnquoteSplicing(_) if self.depth == 0 => {
+                panic!("`,@` outside of a list at {}: {}", node.span, node)
+            }
+            _ => return visit::walk_node_mut(self, node),
+        };
+        *node = value;
+    }
+
+    fn visit_s(&mut self, ns: &mut Rc<Vec<Node>>, _span: Span) {
+        self.splice(ns)
+    }
+
+    fn visit_vector(&mut self, ns: &mut Rc<Vec<Node>>, _span: Span) {
+        self.splice(ns)
+    }
+
+    fn visit_quasiquote(&mut self, n: &mut Rc<Node>, _span: Span) {
+        self.depth += 1;
+        visit::walk_rc_mut(self, n);
+        self.depth -= 1;
+    }
+
+    // Only reached inside a nested quasiquote.
+    fn visit_unquote(&mut self, n: &mut Rc<Node>, _span: Span) {
+        self.nested(n)
+    }
+
+    fn visit_unquote_splicing(&mut self, n: &mut Rc<Node>, _span: Span) {
+        self.nested(n)
     }
 }
 
-fn run_args(s: &Node, envr: &Envr) -> Vec<Node> {
-    if let &Node::S(ref ns) = s {
+// Evaluates the arguments of s, i.e., every element except the first.
+pub fn run_args(s: &Node, envr: &Envr) -> Vec<Node> {
+    if let NodeKind::S(ref ns) = s.kind {
         return ns[1..].iter().map(|n| run_node(n, envr)).collect();
     }
 
@@ -166,12 +245,11 @@ fn run_args(s: &Node, envr: &Envr) -> Vec<Node> {
 mod test {
     use super::{run_node, Envr};
     use super::*;
-    use parser::Node;
-    use lexer::Str;
+    use symbol::Symbol;
 
     #[test]
     fn test_empty() {
-        assert!(run_program(&program!()) == vec![]);
+        assert!(run_program(&program!()).is_empty());
     }
 
     #[test]
@@ -180,7 +258,7 @@ mod test {
                 vec![lit_str!("foo"), lit_num!(42)]);
         let envr = &Envr::new();
         assert!(run_node(&lit_str!("foo"), envr) == lit_str!("foo"));
-        let s = s!(Node::Fn, ident!("x"), s!(Node::Plus, ident!("x"), lit_num!(42)));
+        let s = s!(kw!("fn"), ident!("x"), s!(kw!("+"), ident!("x"), lit_num!(42)));
         assert!(run_node(&s, envr) == s);
         let s = s!();
         assert!(run_node(&s, envr) == s);
@@ -191,33 +269,56 @@ mod test {
         let envr = &Envr::new();
         let s = s!(s!(), s!(lit_num!(42)));
         assert!(run_node(&s, envr) == lit_num!(42));        
-        let s = s!(s!(Node::Plus, lit_num!(42)));
+        let s = s!(s!(kw!("+"), lit_num!(42)));
         assert!(run_node(&s, envr) == lit_num!(42));        
     }
 
     #[test]
     fn test_print() {
         let envr = &Envr::new();
-        let s = s!(Node::Print, lit_str!("Hello world!"));
+        let s = s!(kw!("print"), lit_str!("Hello world!"));
         assert!(run_node(&s, envr) == s!());
     }    
 
     #[test]
     fn test_plus() {
         let envr = &Envr::new();
-        let s = s!(Node::Plus, lit_num!(3));
+        let s = s!(kw!("+"), lit_num!(3));
         assert!(run_node(&s, envr) == lit_num!(3));
-        let s = s!(Node::Plus, lit_num!(3), lit_num!(1));
+        let s = s!(kw!("+"), lit_num!(3), lit_num!(1));
         assert!(run_node(&s, envr) == lit_num!(4));
-        let s = s!(Node::Plus, lit_num!(3), lit_num!(1), lit_num!(1), lit_num!(1));
+        let s = s!(kw!("+"), lit_num!(3), lit_num!(1), lit_num!(1), lit_num!(1));
         assert!(run_node(&s, envr) == lit_num!(6));
     }    
 
+    #[test]
+    fn test_plus_numbers() {
+        let envr = &Envr::new();
+        let s = s!(kw!("+"), lit_num!(3), lit_num!(-5));
+        assert!(run_node(&s, envr) == lit_num!(-2));
+        let s = s!(kw!("+"), lit_num!(1), lit_float!(0.5));
+        assert!(run_node(&s, envr) == lit_float!(1.5));
+        let s = s!(kw!("+"), lit_float!(0.5), lit_num!(1), lit_float!(0.25));
+        assert!(run_node(&s, envr) == lit_float!(1.75));
+        let s = s!(kw!("+"), lit_num!(4000000000), lit_num!(4000000000));
+        assert!(run_node(&s, envr) == lit_num!(8000000000));
+        let s = s!(kw!("+"));
+        assert!(run_node(&s, envr) == lit_num!(0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_plus_overflow() {
+        let envr = &Envr::new();
+        let s = s!(kw!("+"), lit_num!(9223372036854775807), lit_num!(1));
+        run_node(&s, envr);
+    }
+
     #[test]
     #[should_panic]
     fn test_plus_fail() {
         let envr = &Envr::new();
-        let s = s!(Node::Plus, lit_num!(3), s!());
+        let s = s!(kw!("+"), lit_num!(3), s!());
         run_node(&s, envr);
     }    
 
@@ -239,32 +340,32 @@ mod test {
         let envr = &Envr::new();
         {
             let _guard = envr.push_rib();
-            envr.store(&Str::new("x"), lit_num!(0));
+            envr.store(Symbol::intern("x"), lit_num!(0));
             {
                 let _guard = envr.push_rib();
-                envr.store(&Str::new("x"), lit_num!(42));
-                assert!(envr.lookup(&Str::new("x")) == Some(lit_num!(42)));
-                assert!(envr.lookup(&Str::new("y")) == None);
+                envr.store(Symbol::intern("x"), lit_num!(42));
+                assert!(envr.lookup(Symbol::intern("x")) == Some(lit_num!(42)));
+                assert!(envr.lookup(Symbol::intern("y")) == None);
             }
-            assert!(envr.lookup(&Str::new("x")) == Some(lit_num!(0)));
-            assert!(envr.lookup(&Str::new("y")) == None);
+            assert!(envr.lookup(Symbol::intern("x")) == Some(lit_num!(0)));
+            assert!(envr.lookup(Symbol::intern("y")) == None);
         }
-        assert!(envr.lookup(&Str::new("x")) == None);
-        assert!(envr.lookup(&Str::new("y")) == None);
+        assert!(envr.lookup(Symbol::intern("x")) == None);
+        assert!(envr.lookup(Symbol::intern("y")) == None);
     }
 
     #[test]
     #[should_panic]
     fn test_ident_dup_fail() {
         let envr = &Envr::with_value("x", lit_num!(42));
-        envr.store(&Str::new("x"), lit_num!(42));
+        envr.store(Symbol::intern("x"), lit_num!(42));
     }
 
     #[test]
     fn test_scoped_ident() {
         let envr = &Envr::with_value("x", lit_num!(0));
         let _guard = envr.push_rib();
-        envr.store(&Str::new("x"), lit_num!(42));
+        envr.store(Symbol::intern("x"), lit_num!(42));
         assert!(run_node(&ident!("x"), envr) == lit_num!(42));
     }
 
@@ -272,28 +373,28 @@ mod test {
     fn test_let() {
         let envr = &Envr::new();
         // trivial
-        assert!(run_node(&s!(Node::Let, s!()), envr) == s!());
-        assert!(run_node(&s!(Node::Let, lit_num!(42)), envr) == lit_num!(42));
+        assert!(run_node(&s!(kw!("let"), s!()), envr) == s!());
+        assert!(run_node(&s!(kw!("let"), lit_num!(42)), envr) == lit_num!(42));
         // easy
-        assert!(run_node(&s!(Node::Let, ident!("x"), lit_num!(42), ident!("x")), envr) ==
+        assert!(run_node(&s!(kw!("let"), ident!("x"), lit_num!(42), ident!("x")), envr) ==
                 lit_num!(42));
-        assert!(run_node(&s!(Node::Let, ident!("x"), lit_num!(42),
-                                        s!(Node::Plus, ident!("x"), lit_num!(42))), envr) ==
+        assert!(run_node(&s!(kw!("let"), ident!("x"), lit_num!(42),
+                                        s!(kw!("+"), ident!("x"), lit_num!(42))), envr) ==
                 lit_num!(84));
         // multiple
-        assert!(run_node(&s!(Node::Let, ident!("x"), lit_num!(3),
+        assert!(run_node(&s!(kw!("let"), ident!("x"), lit_num!(3),
                                         ident!("y"), lit_num!(4),
-                                        s!(Node::Plus, ident!("x"), ident!("y"))), envr) ==
+                                        s!(kw!("+"), ident!("x"), ident!("y"))), envr) ==
                 lit_num!(7));
         // scoped
-        assert!(run_node(&s!(Node::Let, ident!("x"), lit_num!(0),
-                                        s!(Node::Let, ident!("x"), lit_num!(42),
+        assert!(run_node(&s!(kw!("let"), ident!("x"), lit_num!(0),
+                                        s!(kw!("let"), ident!("x"), lit_num!(42),
                                                       ident!("x"))), envr) ==
                 lit_num!(42));
         // uses earlier
-        assert!(run_node(&s!(Node::Let, ident!("x"), lit_num!(3),
-                                        ident!("y"), s!(Node::Plus, ident!("x"), lit_num!(1)),
-                                        s!(Node::Plus, ident!("x"), ident!("y"))), envr) ==
+        assert!(run_node(&s!(kw!("let"), ident!("x"), lit_num!(3),
+                                        ident!("y"), s!(kw!("+"), ident!("x"), lit_num!(1)),
+                                        s!(kw!("+"), ident!("x"), ident!("y"))), envr) ==
                 lit_num!(7));
     }
 
@@ -301,39 +402,39 @@ mod test {
     #[should_panic]
     fn test_let_not_rec() {
         let envr = &Envr::new();
-        run_node(&s!(Node::Let, ident!("x"), s!(Node::Plus, ident!("x"), lit_num!(0)), s!()), envr);
+        run_node(&s!(kw!("let"), ident!("x"), s!(kw!("+"), ident!("x"), lit_num!(0)), s!()), envr);
     }
 
     #[test]
     fn test_fn() {
         let envr = &Envr::new();
         // trivial
-        assert!(run_node(&s!(s!(Node::Fn, s!())), envr) == s!());
-        assert!(run_node(&s!(s!(Node::Fn, lit_num!(42))), envr) == lit_num!(42));
+        assert!(run_node(&s!(s!(kw!("fn"), s!())), envr) == s!());
+        assert!(run_node(&s!(s!(kw!("fn"), lit_num!(42))), envr) == lit_num!(42));
         // easy
-        assert!(run_node(&s!(s!(Node::Fn, ident!("x"), ident!("x")), lit_num!(42)), envr) == lit_num!(42));
-        assert!(run_node(&s!(s!(Node::Fn, ident!("x"), lit_num!(42)), lit_num!(0)), envr) == lit_num!(42));
-        assert!(run_node(&s!(s!(Node::Fn, ident!("x"), s!(Node::Plus, ident!("x"), lit_num!(1))), lit_num!(42)), envr) == lit_num!(43));
+        assert!(run_node(&s!(s!(kw!("fn"), ident!("x"), ident!("x")), lit_num!(42)), envr) == lit_num!(42));
+        assert!(run_node(&s!(s!(kw!("fn"), ident!("x"), lit_num!(42)), lit_num!(0)), envr) == lit_num!(42));
+        assert!(run_node(&s!(s!(kw!("fn"), ident!("x"), s!(kw!("+"), ident!("x"), lit_num!(1))), lit_num!(42)), envr) == lit_num!(43));
         // multiple args
-        assert!(run_node(&s!(s!(Node::Fn, ident!("x"), ident!("y"), ident!("x")), lit_num!(42), lit_num!(0)), envr) == lit_num!(42));
-        assert!(run_node(&s!(s!(Node::Fn, ident!("x"), ident!("y"),
-                                          s!(Node::Plus, ident!("x"), ident!("y"))),
+        assert!(run_node(&s!(s!(kw!("fn"), ident!("x"), ident!("y"), ident!("x")), lit_num!(42), lit_num!(0)), envr) == lit_num!(42));
+        assert!(run_node(&s!(s!(kw!("fn"), ident!("x"), ident!("y"),
+                                          s!(kw!("+"), ident!("x"), ident!("y"))),
                              lit_num!(42), lit_num!(1)), envr) == lit_num!(43));
         // scopes
-        let f1 = s!(Node::Fn, ident!("x"), s!(Node::Plus, ident!("x"), lit_num!(1)));
-        let f2 = s!(Node::Fn, ident!("x"), s!(f1, s!(Node::Plus, ident!("x"), lit_num!(4))));
+        let f1 = s!(kw!("fn"), ident!("x"), s!(kw!("+"), ident!("x"), lit_num!(1)));
+        let f2 = s!(kw!("fn"), ident!("x"), s!(f1, s!(kw!("+"), ident!("x"), lit_num!(4))));
         assert!(run_node(&s!(f2, lit_num!(2)), envr) == lit_num!(7));
         // higher order
-        let f1 = s!(Node::Fn, ident!("x"), ident!("y"), s!(ident!("x"), s!(Node::Plus, ident!("y"), lit_num!(3))));
-        let f2 = s!(Node::Fn, ident!("x"), s!(Node::Plus, ident!("x"), lit_num!(2)));
+        let f1 = s!(kw!("fn"), ident!("x"), ident!("y"), s!(ident!("x"), s!(kw!("+"), ident!("y"), lit_num!(3))));
+        let f2 = s!(kw!("fn"), ident!("x"), s!(kw!("+"), ident!("x"), lit_num!(2)));
         assert!(run_node(&s!(f1, f2, lit_num!(5)), envr) == lit_num!(10));
     }
 
     #[test]
     fn test_fn_let() {
         let envr = &Envr::new();
-        let f = s!(Node::Fn, ident!("x"), s!(Node::Plus, ident!("x"), lit_num!(1)));
-        let l = s!(Node::Let, ident!("y"), f, s!(ident!("y"), lit_num!(42)));
+        let f = s!(kw!("fn"), ident!("x"), s!(kw!("+"), ident!("x"), lit_num!(1)));
+        let l = s!(kw!("let"), ident!("y"), f, s!(ident!("y"), lit_num!(42)));
         assert!(run_node(&l, envr) == lit_num!(43));
     }
 
@@ -341,6 +442,80 @@ mod test {
     #[should_panic]
     fn test_fn_arg_mismatch() {
         let envr = &Envr::new();
-        run_node(&s!(s!(Node::Fn, ident!("x"), ident!("x")), lit_num!(42), lit_num!(42)), envr);
+        run_node(&s!(s!(kw!("fn"), ident!("x"), ident!("x")), lit_num!(42), lit_num!(42)), envr);
+    }
+
+    #[test]
+    fn test_quote() {
+        let envr = &Envr::new();
+        assert!(run_node(&quote!(ident!("x")), envr) == ident!("x"));
+        let s = s!(kw!("+"), lit_num!(1), lit_num!(2));
+        assert!(run_node(&quote!(s.clone()), envr) == s);
+        assert!(run_node(&quote!(quote!(ident!("x"))), envr) == quote!(ident!("x")));
+        // Quoted data can be passed around like any other value.
+        let l = s!(kw!("let"), ident!("x"), quote!(s!(ident!("a"), ident!("b"))), ident!("x"));
+        assert!(run_node(&l, envr) == s!(ident!("a"), ident!("b")));
+    }
+
+    #[test]
+    fn test_quasiquote() {
+        let envr = &Envr::with_value("x", lit_num!(42));
+        assert!(run_node(&quasiquote!(ident!("x")), envr) == ident!("x"));
+        assert!(run_node(&quasiquote!(unquote!(ident!("x"))), envr) == lit_num!(42));
+        let t = quasiquote!(s!(ident!("a"), unquote!(ident!("x")), unquote!(s!(kw!("+"), ident!("x"), lit_num!(1)))));
+        assert!(run_node(&t, envr) == s!(ident!("a"), lit_num!(42), lit_num!(43)));
+        let t = quasiquote!(s!(ident!("a"), unquote_splicing!(quote!(s!(lit_num!(1), lit_num!(2)))), ident!("b")));
+        assert!(run_node(&t, envr) == s!(ident!("a"), lit_num!(1), lit_num!(2), ident!("b")));
+        let t = quasiquote!(s!(unquote_splicing!(quote!(s!()))));
+        assert!(run_node(&t, envr) == s!());
+        // Quotes don't stop unquoting.
+        let t = quasiquote!(quote!(unquote!(ident!("x"))));
+        assert!(run_node(&t, envr) == quote!(lit_num!(42)));
+        // Nested quasiquotes.
+        let t = quasiquote!(s!(ident!("a"), quasiquote!(s!(unquote!(ident!("x")), unquote!(unquote!(ident!("x")))))));
+        assert!(run_node(&t, envr) ==
+                s!(ident!("a"), quasiquote!(s!(unquote!(ident!("x")), unquote!(lit_num!(42))))));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_unquote_fail() {
+        let envr = &Envr::with_value("x", lit_num!(42));
+        run_node(&unquote!(ident!("x")), envr);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_splice_fail() {
+        let envr = &Envr::with_value("x", lit_num!(42));
+        run_node(&quasiquote!(s!(unquote_splicing!(ident!("x")))), envr);
+    }
+
+    #[test]
+    fn test_collections() {
+        let envr = &Envr::with_value("x", lit_num!(42));
+        let v = vector!(lit_num!(1), lit_str!("a"));
+        assert!(v.is_value());
+        assert!(run_node(&v, envr) == v);
+        let v = vector!(ident!("x"), s!(kw!("+"), ident!("x"), lit_num!(1)), vector!(ident!("x")));
+        assert!(!v.is_value());
+        assert!(run_node(&v, envr) == vector!(lit_num!(42), lit_num!(43), vector!(lit_num!(42))));
+        let m = map!(lit_str!("x") => ident!("x"), ident!("x") => quote!(ident!("y")));
+        assert!(run_node(&m, envr) == map!(lit_str!("x") => lit_num!(42), lit_num!(42) => ident!("y")));
+        // Templates.
+        let t = quasiquote!(vector!(ident!("a"), unquote!(ident!("x")),
+                                    unquote_splicing!(quote!(vector!(lit_num!(1), lit_num!(2))))));
+        assert!(run_node(&t, envr) == vector!(ident!("a"), lit_num!(42), lit_num!(1), lit_num!(2)));
+        let t = quasiquote!(map!(ident!("k") => unquote!(ident!("x"))));
+        assert!(run_node(&t, envr) == map!(ident!("k") => lit_num!(42)));
+    }
+
+    #[test]
+    fn test_bools_chars() {
+        let envr = &Envr::new();
+        assert!(run_node(&lit_bool!(true), envr) == lit_bool!(true));
+        assert!(run_node(&lit_char!('a'), envr) == lit_char!('a'));
+        let l = s!(kw!("let"), ident!("b"), lit_bool!(false), vector!(ident!("b"), lit_char!(' ')));
+        assert!(run_node(&l, envr) == vector!(lit_bool!(false), lit_char!(' ')));
     }
 }
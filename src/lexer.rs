@@ -1,40 +1,211 @@
 use std::fmt;
 use std::ops::Deref;
-use std::str::Chars;
-use std::iter::{Iterator, Peekable};
-use KEYWORDS;
+use std::str;
+use std::io::{self, Read};
+use std::borrow::Cow;
+use std::cmp;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::rc::Rc;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use forms;
+use symbol::Symbol;
+
+// Source locations.
+
+// A region of the input. `lo` and `hi` are byte offsets (`hi` is exclusive),
+// `line` and `col` give the 1-based position of `lo`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Serialize, Deserialize)]
+pub struct Span {
+    pub lo: usize,
+    pub hi: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+// Used for nodes which don't come from the source, e.g., in tests.
+pub const DUMMY_SP: Span = Span { lo: 0, hi: 0, line: 0, col: 0 };
+
+// The default span when deserializing.
+pub fn dummy_span() -> Span {
+    DUMMY_SP
+}
+
+impl Span {
+    pub fn new(lo: usize, hi: usize, line: usize, col: usize) -> Span {
+        Span {
+            lo: lo,
+            hi: hi,
+            line: line,
+            col: col,
+        }
+    }
+
+    // A span from the start of self to the end of other.
+    pub fn to(&self, other: Span) -> Span {
+        Span {
+            hi: other.hi,
+            ..*self
+        }
+    }
+
+    // True if other is within self.
+    pub fn contains(&self, other: Span) -> bool {
+        self.lo <= other.lo && other.hi <= self.hi
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
 
 // Token defintions.
 
-#[derive(Clone, PartialEq, Eq, Debug)]
-pub enum Token {
+// See the README for how tokens are serialized.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value")]
+pub enum TokenKind {
     Bra,
     Ket,
-    Keyword(&'static str),
+    // `[` and `]`, for vectors.
+    SquareBra,
+    SquareKet,
+    // `{` and `}`, for maps.
+    CurlyBra,
+    CurlyKet,
+    // Reader shorthand: `'`, `` ` ``, `,`, and `,@`.
+    Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
+    Keyword(#[serde(deserialize_with = "forms::deserialize_keyword")] forms::Keyword),
     Str(Str),
-    Number(u32),
-    Name(Str),
+    Number(i64),
+    Float(f64),
+    // `#t` or `#f` (or `#true` or `#false`).
+    Bool(bool),
+    // `#\a`, `#\space`, or `#\x3bb`.
+    Char(char),
+    Name(Symbol),
+    // Only produced by lex_with_comments and lex_lossless. Contains the whole
+    // text of the comment, including delimiters.
+    Comment(Str),
+    // Only produced by lex_lossless.
+    Whitespace(Str),
 }
 
-impl fmt::Display for Token {
+impl TokenKind {
+    // If self is an opening delimiter, the matching closing delimiter.
+    pub fn closing(&self) -> Option<TokenKind> {
+        match *self {
+            TokenKind::Bra => Some(TokenKind::Ket),
+            TokenKind::SquareBra => Some(TokenKind::SquareKet),
+            TokenKind::CurlyBra => Some(TokenKind::CurlyKet),
+            _ => None,
+        }
+    }
+
+    pub fn is_closing(&self) -> bool {
+        match *self {
+            TokenKind::Ket | TokenKind::SquareKet | TokenKind::CurlyKet => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for TokenKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Token::Bra => '('.fmt(f),
-            Token::Ket => ')'.fmt(f),
-            Token::Keyword(ref s) => s.fmt(f),
-            Token::Str(ref s) => s.fmt(f),
-            Token::Number(n) => n.fmt(f),
-            Token::Name(ref s) => s.fmt(f),
+            TokenKind::Bra => '('.fmt(f),
+            TokenKind::Ket => ')'.fmt(f),
+            TokenKind::SquareBra => '['.fmt(f),
+            TokenKind::SquareKet => ']'.fmt(f),
+            TokenKind::CurlyBra => '{'.fmt(f),
+            TokenKind::CurlyKet => '}'.fmt(f),
+            TokenKind::Quote => '\''.fmt(f),
+            TokenKind::Quasiquote => '`'.fmt(f),
+            TokenKind::Unquote => ','.fmt(f),
+            TokenKind::UnquoteSplicing => ",@".fmt(f),
+            TokenKind::Keyword(ref s) => s.fmt(f),
+            TokenKind::Str(ref s) => str_literal(s).fmt(f),
+            TokenKind::Number(n) => n.fmt(f),
+            // Debug formatting always includes a `.` or exponent.
+            TokenKind::Float(n) => write!(f, "{:?}", n),
+            TokenKind::Bool(true) => "#t".fmt(f),
+            TokenKind::Bool(false) => "#f".fmt(f),
+            TokenKind::Char(c) => char_literal(c).fmt(f),
+            TokenKind::Name(ref s) => s.fmt(f),
+            TokenKind::Comment(ref s) => s.fmt(f),
+            TokenKind::Whitespace(ref s) => s.fmt(f),
         }
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug, Hash)]
-pub struct Str(String);
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Token {
+    #[serde(flatten)]
+    pub kind: TokenKind,
+    #[serde(default = "dummy_span")]
+    pub span: Span,
+}
+
+impl Token {
+    pub fn new(kind: TokenKind, span: Span) -> Token {
+        Token {
+            kind: kind,
+            span: span,
+        }
+    }
+}
+
+// Tokens are compared structurally, spans are ignored.
+impl PartialEq for Token {
+    fn eq(&self, other: &Token) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl PartialEq<TokenKind> for Token {
+    fn eq(&self, other: &TokenKind) -> bool {
+        self.kind == *other
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+// The text of a string, comment, or whitespace token. The lexer takes it as a
+// slice of its input without copying; only strings with escapes are copied.
+#[derive(Clone)]
+pub struct Str {
+    src: Rc<str>,
+    lo: usize,
+    hi: usize,
+}
 
 impl Str {
     pub fn new(s: &str) -> Str {
-        Str(s.to_owned())
+        Str::owned(s.to_owned())
+    }
+
+    fn owned(s: String) -> Str {
+        let hi = s.len();
+        Str::slice(Rc::from(s), 0, hi)
+    }
+
+    // The text src[lo..hi], which shares src.
+    fn slice(src: Rc<str>, lo: usize, hi: usize) -> Str {
+        Str {
+            src: src,
+            lo: lo,
+            hi: hi,
+        }
     }
 }
 
@@ -42,127 +213,955 @@ impl Deref for Str {
     type Target = str;
 
     fn deref(&self) -> &str {
-        &&self.0
+        &self.src[self.lo..self.hi]
+    }
+}
+
+impl PartialEq for Str {
+    fn eq(&self, other: &Str) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Str {}
+
+impl Hash for Str {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
     }
 }
 
 impl fmt::Display for Str {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.0.fmt(f)
+        (**self).fmt(f)
+    }
+}
+
+impl fmt::Debug for Str {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Str({:?})", &**self)
+    }
+}
+
+impl Serialize for Str {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Str {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Str, D::Error> {
+        let s = try!(String::deserialize(d));
+        Ok(Str::owned(s))
+    }
+}
+
+
+// Errors.
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LexErrorKind {
+    // A string literal with no closing `"`.
+    UnterminatedString,
+    // A `#|` with no matching `|#`, or a `#;` with an unclosed datum.
+    UnterminatedComment,
+    // A `#;` which is not followed by a datum.
+    MissingDatum,
+    // A `\` followed by a char which is not a known escape.
+    UnknownEscape(char),
+    // A malformed `\u{...}` escape.
+    InvalidUnicodeEscape,
+    // A number literal which doesn't fit in an `i64` (or an `f64`), includes
+    // the literal.
+    NumberOverflow(String),
+    // A character which may not appear outside a string literal.
+    DisallowedChar(char),
+    // A `#\` which is not followed by a single char, a char name, or a hex
+    // code, includes the text after `#\`.
+    InvalidCharLiteral(String),
+    // Reading the input failed (including because it is not UTF-8), includes
+    // the error message. Lexing stops at the failure.
+    Io(String),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)
+    }
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LexErrorKind::UnterminatedString => write!(f, "unterminated string literal"),
+            LexErrorKind::UnterminatedComment => write!(f, "unterminated comment"),
+            LexErrorKind::MissingDatum => write!(f, "expected an expression after `#;`"),
+            LexErrorKind::UnknownEscape(c) => write!(f, "unknown escape: \\{}", c),
+            LexErrorKind::InvalidUnicodeEscape => write!(f, "invalid unicode escape"),
+            LexErrorKind::NumberOverflow(ref s) => write!(f, "number literal out of range: {}", s),
+            LexErrorKind::DisallowedChar(c) => write!(f, "disallowed character: {:?}", c),
+            LexErrorKind::InvalidCharLiteral(ref s) => write!(f, "invalid character literal: #\\{}", s),
+            LexErrorKind::Io(ref s) => write!(f, "error reading input: {}", s),
+        }
     }
 }
 
 
 // Lexing.
 
-pub fn lex(input: &str) -> Vec<Token> {
+// Lexes the whole input. If there are any errors, returns all of them rather
+// than any tokens. Comments are dropped.
+pub fn lex(input: &str) -> Result<Vec<Token>, Vec<LexError>> {
+    lex_impl(input, false, false)
+}
+
+// Like lex, but comments are returned as TokenKind::Comment tokens.
+pub fn lex_with_comments(input: &str) -> Result<Vec<Token>, Vec<LexError>> {
+    lex_impl(input, true, false)
+}
+
+// Like lex, but keeps comments and whitespace. The spans of the tokens cover
+// the whole input without gaps, so the input can be reconstructed exactly.
+pub fn lex_lossless(input: &str) -> Result<Vec<Token>, Vec<LexError>> {
+    lex_impl(input, true, true)
+}
+
+fn lex_impl(input: &str, keep_comments: bool, keep_whitespace: bool) -> Result<Vec<Token>, Vec<LexError>> {
     let mut lexer = Lexer::new(input);
-    lexer.lex()
+    lexer.keep_comments = keep_comments;
+    lexer.keep_whitespace = keep_whitespace;
+    let mut result = Vec::new();
+    let mut errors = Vec::new();
+    for t in lexer {
+        match t {
+            Ok(t) => result.push(t),
+            Err(e) => errors.push(e),
+        }
+    }
+    if errors.is_empty() {
+        Ok(result)
+    } else {
+        Err(errors)
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+}
+
+// How much input to read at a time when lexing from a reader.
+const READ_SIZE: usize = 8 * 1024;
+
+// A lexer is an iterator of tokens (and errors, in the order they are found).
+//
+// The lexer scans the UTF-8 bytes of its input directly, and takes the text of
+// names, strings, and comments as slices of the input. The input is kept in a
+// shared buffer which the Strs in the tokens point into, so their text is not
+// copied. When lexing from a reader, it only reads as far ahead as it needs to
+// finish the current token, so it can lex input which is still arriving. If it
+// runs out of input part way through a token, it reads some more and lexes the
+// token again. Each read copies the unread text and the new input into a new
+// buffer, tokens which point into the old one keep it alive.
+//
+// Comments and whitespace are dropped.
+pub struct Lexer<R> {
+    // The input which has been read, from byte offset base. When lexing from
+    // a reader, text before the current token is dropped as we go.
+    src: Rc<str>,
+    base: usize,
+    // None once all of the input has been read.
+    reader: Option<R>,
+    // Bytes which have been read but are not a complete UTF-8 char yet.
+    partial: Vec<u8>,
+    // Set if we tried to look past the end of src before the end of the input.
+    starved: bool,
+    // Byte offset of the next char.
+    pos: usize,
+    line: usize,
+    col: usize,
+    errors: Vec<LexError>,
+    // A token which is ready, but must wait until errors have been returned.
+    token: Option<Token>,
+    keep_comments: bool,
+    keep_whitespace: bool,
 }
 
-struct Lexer<'a> {
-    iter: Peekable<Chars<'a>>,
+impl Lexer<io::Empty> {
+    pub fn new(input: &str) -> Lexer<io::Empty> {
+        Lexer::with_source(Rc::from(input), None)
+    }
+}
+
+impl<R: Read> Lexer<R> {
+    pub fn from_reader(input: R) -> Lexer<R> {
+        Lexer::with_source(Rc::from(""), Some(input))
+    }
+}
+
+impl<R: Read> Iterator for Lexer<R> {
+    type Item = Result<Token, LexError>;
+
+    fn next(&mut self) -> Option<Result<Token, LexError>> {
+        loop {
+            if !self.errors.is_empty() {
+                return Some(Err(self.errors.remove(0)));
+            }
+            if let Some(t) = self.token.take() {
+                return Some(Ok(t));
+            }
+
+            let (pos, line, col, errors) = (self.pos, self.line, self.col, self.errors.len());
+            self.starved = false;
+            if !self.done() {
+                let token = self.next_token();
+                if !self.starved {
+                    self.token = token;
+                    continue;
+                }
+            } else if !self.starved {
+                return None;
+            }
+
+            // We ran out of input, read some more and start the token again.
+            self.pos = pos;
+            self.line = line;
+            self.col = col;
+            self.errors.truncate(errors);
+            self.fill();
+        }
+    }
 }
 
-impl<'a> Lexer<'a> {
-    fn new(input: &'a str) -> Lexer<'a> {
+impl<R: Read> Lexer<R> {
+    fn with_source(src: Rc<str>, reader: Option<R>) -> Lexer<R> {
         Lexer {
-            iter: input.chars().peekable(),
+            src: src,
+            base: 0,
+            reader: reader,
+            partial: Vec::new(),
+            starved: false,
+            pos: 0,
+            line: 1,
+            col: 1,
+            errors: Vec::new(),
+            token: None,
+            keep_comments: false,
+            keep_whitespace: false,
         }
     }
 
-    fn lex(&mut self) -> Vec<Token> {
-        let mut result = vec![];
+    // Reads more input, dropping the text before the current position. A read
+    // error is recorded and treated as the end of the input.
+    fn fill(&mut self) {
+        let mut reader = match self.reader.take() {
+            Some(reader) => reader,
+            None => return,
+        };
 
-        while !self.done() {
-            let t = self.next_token();
-            result.extend(t.into_iter());
-        }
+        // Read at least as much as we have, so that a long token doesn't take
+        // quadratic time.
+        let mut buf = mem::replace(&mut self.partial, Vec::new());
+        let start = buf.len();
+        let len = self.src.len() - (self.pos - self.base);
+        buf.resize(start + cmp::max(READ_SIZE, len), 0);
+        let error = match reader.read(&mut buf[start..]) {
+            Ok(0) if start == 0 => return,
+            Ok(0) => invalid_utf8(),
+            Ok(n) => {
+                buf.truncate(start + n);
+                match str::from_utf8(&buf) {
+                    Ok(s) => {
+                        self.append(s);
+                        self.reader = Some(reader);
+                        return;
+                    }
+                    Err(e) => {
+                        let valid = e.valid_up_to();
+                        self.append(str::from_utf8(&buf[..valid]).unwrap());
+                        if e.error_len().is_none() {
+                            // The end of the buffer is part of a char.
+                            self.partial = buf[valid..].to_vec();
+                            self.reader = Some(reader);
+                            return;
+                        }
+                        invalid_utf8()
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+                self.reader = Some(reader);
+                return;
+            }
+            Err(e) => e,
+        };
+
+        let span = self.cur_span();
+        self.error(LexErrorKind::Io(error.to_string()), span);
+    }
 
-        result
+    // Replaces src with the text from the current position followed by s.
+    // Tokens which have already been lexed keep the old src alive.
+    fn append(&mut self, s: &str) {
+        let mut src = String::with_capacity(self.src.len() - (self.pos - self.base) + s.len());
+        src.push_str(&self.src[self.pos - self.base..]);
+        src.push_str(s);
+        self.src = Rc::from(src);
+        self.base = self.pos;
     }
 
     fn done(&mut self) -> bool {
-        self.iter.peek().is_none()
+        self.peek_byte().is_none()
+    }
+
+    // The byte at offset i from the current position.
+    fn byte_at(&mut self, i: usize) -> Option<u8> {
+        let i = self.pos - self.base + i;
+        match self.src.as_bytes().get(i) {
+            Some(&b) => Some(b),
+            None => {
+                self.starved |= self.reader.is_some();
+                None
+            }
+        }
+    }
+
+    fn peek_byte(&mut self) -> Option<u8> {
+        self.byte_at(0)
+    }
+
+    // The char starting at offset i from the current position.
+    fn char_at(&mut self, i: usize) -> Option<char> {
+        match self.byte_at(i) {
+            Some(b) if b < 0x80 => Some(b as char),
+            Some(_) => self.src[self.pos - self.base + i..].chars().next(),
+            None => None,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.char_at(0)
+    }
+
+    // The char after the next one.
+    fn peek2(&mut self) -> Option<char> {
+        self.peek_nth(1)
     }
 
+    // The nth char after the current position (which is 0).
+    fn peek_nth(&mut self, n: usize) -> Option<char> {
+        let mut i = 0;
+        for _ in 0..n {
+            match self.char_at(i) {
+                Some(c) => i += c.len_utf8(),
+                None => return None,
+            }
+        }
+        self.char_at(i)
+    }
+
+    // Consume the next char, keeping track of where we are in the input.
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        if let Some(c) = c {
+            self.pos += c.len_utf8();
+            if c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
+        c
+    }
+
+    // Consume bytes up to offset i from the current position, which must be
+    // at a char boundary.
+    fn bump_bytes(&mut self, i: usize) {
+        let lo = self.pos - self.base;
+        for &b in &self.src.as_bytes()[lo..lo + i] {
+            if b == b'\n' {
+                self.line += 1;
+                self.col = 1;
+            } else if b & 0xc0 != 0x80 {
+                // Not a UTF-8 continuation byte, so the start of a char.
+                self.col += 1;
+            }
+        }
+        self.pos += i;
+    }
+
+    // The offset from the current position of the end of a name (or char
+    // name) starting at offset i, i.e., the next whitespace, delimiter, or
+    // disallowed char.
+    fn name_end(&mut self, i: usize) -> usize {
+        let mut i = i;
+        loop {
+            match self.byte_at(i) {
+                Some(b) if b < 0x80 => {
+                    let c = b as char;
+                    if c.is_whitespace() || is_delimiter(c) || is_disallowed(c) {
+                        return i;
+                    }
+                    i += 1;
+                }
+                Some(_) => {
+                    let c = self.char_at(i).unwrap();
+                    if c.is_whitespace() || is_disallowed(c) {
+                        return i;
+                    }
+                    i += c.len_utf8();
+                }
+                None => return i,
+            }
+        }
+    }
+
+    // An empty span at the current position.
+    fn cur_span(&self) -> Span {
+        Span::new(self.pos, self.pos, self.line, self.col)
+    }
+
+    // Returns None at the end of input, for a dropped comment, or if there was
+    // an error (in which case the error is recorded in self.errors).
     fn next_token(&mut self) -> Option<Token> {
+        if self.keep_whitespace && self.peek().map_or(false, |c| c.is_whitespace()) {
+            let start = self.cur_span();
+            self.eat_whitespace();
+            let kind = TokenKind::Whitespace(self.text_from(start));
+            return Some(Token::new(kind, Span { hi: self.pos, ..start }));
+        }
         self.eat_whitespace();
-        let c = self.iter.peek().map(|c| *c);
-        c.map(|c| {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return None,
+        };
+
+        let start = self.cur_span();
+        let kind = match c {
+            '(' => {
+                self.bump();
+                Ok(TokenKind::Bra)
+            }
+            ')' => {
+                self.bump();
+                Ok(TokenKind::Ket)
+            }
+            '[' => {
+                self.bump();
+                Ok(TokenKind::SquareBra)
+            }
+            ']' => {
+                self.bump();
+                Ok(TokenKind::SquareKet)
+            }
+            '{' => {
+                self.bump();
+                Ok(TokenKind::CurlyBra)
+            }
+            '}' => {
+                self.bump();
+                Ok(TokenKind::CurlyKet)
+            }
+            '\'' => {
+                self.bump();
+                Ok(TokenKind::Quote)
+            }
+            '`' => {
+                self.bump();
+                Ok(TokenKind::Quasiquote)
+            }
+            ',' => {
+                self.bump();
+                if self.peek() == Some('@') {
+                    self.bump();
+                    Ok(TokenKind::UnquoteSplicing)
+                } else {
+                    Ok(TokenKind::Unquote)
+                }
+            }
+            '"' => self.lex_string(),
+            ';' => Ok(self.lex_line_comment(start)),
+            '#' if self.peek2() == Some('|') => self.lex_block_comment(start),
+            '#' if self.peek2() == Some(';') => self.lex_datum_comment(start),
+            '#' if self.peek2() == Some('\\') => self.lex_char(),
+            'r' if self.at_raw_string() => self.lex_raw_string(),
+            '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => self.lex_number(),
+            '-' if self.peek2().map_or(false, |c| c.is_digit(10)) => self.lex_number(),
+            c if is_disallowed(c) => {
+                self.bump();
+                Err(LexErrorKind::DisallowedChar(c))
+            }
+            _ => Ok(self.lex_keyword_or_name()),
+        };
+
+        let span = Span { hi: self.pos, ..start };
+        match kind {
+            Ok(TokenKind::Comment(_)) if !self.keep_comments => None,
+            Ok(kind) => Some(Token::new(kind, span)),
+            Err(kind) => {
+                self.errors.push(LexError {
+                    kind: kind,
+                    span: span,
+                });
+                None
+            }
+        }
+    }
+
+    fn eat_whitespace(&mut self) {
+        loop {
+            match self.peek_byte() {
+                Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\x0b') | Some(b'\x0c') => {
+                    self.pos += 1;
+                    self.col += 1;
+                }
+                Some(b'\n') => {
+                    self.pos += 1;
+                    self.line += 1;
+                    self.col = 1;
+                }
+                Some(b) if b < 0x80 => return,
+                Some(_) => {
+                    if !self.peek().unwrap().is_whitespace() {
+                        return;
+                    }
+                    self.bump();
+                }
+                None => return,
+            }
+        }
+    }
+
+    // The source text from start to the current position.
+    fn text_from(&self, start: Span) -> Str {
+        Str::slice(self.src.clone(), start.lo - self.base, self.pos - self.base)
+    }
+
+    // The source text from the byte offset lo (which must be in the current
+    // token) to the current position.
+    fn src_from(&self, lo: usize) -> &str {
+        &self.src[lo - self.base..self.pos - self.base]
+    }
+
+    // Current char is ;, the comment runs to the end of the line.
+    fn lex_line_comment(&mut self, start: Span) -> TokenKind {
+        let mut i = 0;
+        loop {
+            match self.byte_at(i) {
+                Some(b'\n') | None => break,
+                Some(_) => i += 1,
+            }
+        }
+        self.bump_bytes(i);
+
+        TokenKind::Comment(self.text_from(start))
+    }
+
+    // Current chars are #|, block comments may be nested.
+    fn lex_block_comment(&mut self, start: Span) -> Result<TokenKind, LexErrorKind> {
+        // eat #|
+        self.bump();
+        self.bump();
+
+        let mut depth = 1;
+        while let Some(c) = self.bump() {
             match c {
-                '(' => {
-                    self.iter.next();
-                    Token::Bra
+                '#' if self.peek() == Some('|') => {
+                    self.bump();
+                    depth += 1;
                 }
-                ')' => {
-                    self.iter.next();
-                    Token::Ket
+                '|' if self.peek() == Some('#') => {
+                    self.bump();
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(TokenKind::Comment(self.text_from(start)));
+                    }
                 }
-                '"' => self.lex_string(),
-                '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => self.lex_number(),
-                _ => self.lex_keyword_or_name(),
-            }            
-        })
+                _ => {}
+            }
+        }
+
+        Err(LexErrorKind::UnterminatedComment)
     }
 
-    fn eat_whitespace(&mut self) {
-        while let Some(&c) = self.iter.peek() {
-            if !c.is_whitespace() {
-                break;
+    // Current chars are #;, the comment is the next datum, i.e., a single
+    // token or a bracketed list, either of which may be quoted.
+    fn lex_datum_comment(&mut self, start: Span) -> Result<TokenKind, LexErrorKind> {
+        // eat #;
+        self.bump();
+        self.bump();
+
+        let mut depth = 0;
+        loop {
+            self.eat_whitespace();
+            match self.peek() {
+                None if depth == 0 => return Err(LexErrorKind::MissingDatum),
+                None => return Err(LexErrorKind::UnterminatedComment),
+                Some(')') | Some(']') | Some('}') if depth == 0 => return Err(LexErrorKind::MissingDatum),
+                _ => {}
+            }
+
+            // Nested comments (including other datum comments) are skipped
+            // over by next_token, errors are recorded by next_token too.
+            match self.next_token().map(|t| t.kind) {
+                Some(ref k) if k.closing().is_some() => depth += 1,
+                Some(ref k) if k.is_closing() => depth -= 1,
+                Some(TokenKind::Comment(_)) | Some(TokenKind::Whitespace(_)) | None => continue,
+                // The datum comes after the prefix.
+                Some(TokenKind::Quote) |
+                Some(TokenKind::Quasiquote) |
+                Some(TokenKind::Unquote) |
+                Some(TokenKind::UnquoteSplicing) => continue,
+                Some(_) => {}
+            }
+
+            if depth == 0 {
+                return Ok(TokenKind::Comment(self.text_from(start)));
             }
-            self.iter.next();
         }
     }
 
-    // Current char is "; returns Token::Str.
-    fn lex_string(&mut self) -> Token {
+    fn error(&mut self, kind: LexErrorKind, span: Span) {
+        self.errors.push(LexError {
+            kind: kind,
+            span: span,
+        });
+    }
+
+    // Current char is "; returns TokenKind::Str.
+    fn lex_string(&mut self) -> Result<TokenKind, LexErrorKind> {
         // eat "
-        self.iter.next();
+        self.bump();
 
-        let mut result = String::new();
-        while let Some(&c) = self.iter.peek() {
-            self.iter.next();
-            if c == '"' {
-                break;
+        // Strings without escapes are slices of the input, only strings with
+        // escapes are copied.
+        let mut i = 0;
+        loop {
+            match self.byte_at(i) {
+                Some(b'"') => {
+                    let lo = self.pos - self.base;
+                    let result = Str::slice(self.src.clone(), lo, lo + i);
+                    self.bump_bytes(i + 1);
+                    return Ok(TokenKind::Str(result));
+                }
+                Some(b'\\') | None => break,
+                Some(_) => i += 1,
+            }
+        }
+
+        let mut result = String::with_capacity(i);
+        result.push_str(&self.src[self.pos - self.base..self.pos - self.base + i]);
+        self.bump_bytes(i);
+        loop {
+            let start = self.cur_span();
+            match self.bump() {
+                Some('"') => return Ok(TokenKind::Str(Str::owned(result))),
+                Some('\\') if self.done() => break,
+                Some('\\') => {
+                    // Bad escapes don't end the string, we record the error
+                    // and keep going.
+                    match self.lex_escape() {
+                        Ok(c) => result.push(c),
+                        Err(kind) => {
+                            let span = Span { hi: self.pos, ..start };
+                            self.error(kind, span);
+                        }
+                    }
+                }
+                Some(c) => result.push(c),
+                None => break,
             }
-            result.push(c);
         }
 
-        Token::Str(Str(result))
+        Err(LexErrorKind::UnterminatedString)
+    }
+
+    // Just consumed a `\`, returns the escaped char.
+    fn lex_escape(&mut self) -> Result<char, LexErrorKind> {
+        match self.bump() {
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('0') => Ok('\0'),
+            Some('u') => self.lex_unicode_escape(),
+            Some(c) => Err(LexErrorKind::UnknownEscape(c)),
+            None => unreachable!(),
+        }
     }
 
-    // Current char is a numeral; returns Token::Number.
-    fn lex_number(&mut self) -> Token {
-        let mut result = String::new();
-        while let Some(&c) = self.iter.peek() {
-            if !c.is_digit(10) {
+    // Just consumed `\u`, expects `{x}` where x is 1 to 6 hex digits.
+    fn lex_unicode_escape(&mut self) -> Result<char, LexErrorKind> {
+        if self.peek() != Some('{') {
+            return Err(LexErrorKind::InvalidUnicodeEscape);
+        }
+        self.bump();
+
+        let mut digits = String::new();
+        while let Some(c) = self.peek() {
+            if !c.is_digit(16) {
                 break;
             }
-            self.iter.next();
-            result.push(c);
+            self.bump();
+            digits.push(c);
         }
-        
-        Token::Number(result.parse().unwrap())
+
+        if self.peek() != Some('}') {
+            return Err(LexErrorKind::InvalidUnicodeEscape);
+        }
+        self.bump();
+
+        if digits.is_empty() || digits.len() > 6 {
+            return Err(LexErrorKind::InvalidUnicodeEscape);
+        }
+        ::std::char::from_u32(u32::from_str_radix(&digits, 16).unwrap())
+            .ok_or(LexErrorKind::InvalidUnicodeEscape)
     }
 
-    // Returns Token::Keyword or Token::Name.
-    fn lex_keyword_or_name(&mut self) -> Token {
-        let mut result = String::new();
-        while let Some(&c) = self.iter.peek() {
-            if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
-                break;
+    // Current char is r, is this the start of a raw string, i.e., `r"` or
+    // `r#...#"`?
+    fn at_raw_string(&mut self) -> bool {
+        let mut i = 1;
+        loop {
+            match self.peek_nth(i) {
+                Some('#') => i += 1,
+                Some('"') => return true,
+                _ => return false,
             }
-            self.iter.next();
-            result.push(c);
         }
-        
-        if let Ok(index) = KEYWORDS.binary_search(&&*result) {
-            Token::Keyword(KEYWORDS[index])   
+    }
+
+    // Current char is r and at_raw_string is true; returns TokenKind::Str.
+    // A raw string is `r`, any number of `#`s, and a `"`. It ends with a `"`
+    // followed by the same number of `#`s. There are no escapes.
+    fn lex_raw_string(&mut self) -> Result<TokenKind, LexErrorKind> {
+        // eat r
+        self.bump();
+        let mut hashes = 0;
+        while self.bump() == Some('#') {
+            hashes += 1;
+        }
+
+        let lo = self.pos;
+        while let Some(c) = self.bump() {
+            if c == '"' {
+                let mut closing = 0;
+                while closing < hashes && self.peek() == Some('#') {
+                    self.bump();
+                    closing += 1;
+                }
+                if closing == hashes {
+                    let hi = self.pos - 1 - hashes;
+                    return Ok(TokenKind::Str(Str::slice(self.src.clone(), lo - self.base, hi - self.base)));
+                }
+            }
+        }
+
+        Err(LexErrorKind::UnterminatedString)
+    }
+
+    // Current char is a numeral, or a `-` followed by a numeral; returns
+    // TokenKind::Number or TokenKind::Float.
+    //
+    // Integers may have a `0x`, `0b`, or `0o` prefix. Floats are decimal and
+    // have a fractional part (`1.5`), an exponent (`1e10`), or both. Digits
+    // may be separated by `_`s.
+    fn lex_number(&mut self) -> Result<TokenKind, LexErrorKind> {
+        let start = self.pos;
+        if self.peek_byte() == Some(b'-') {
+            self.bump();
+        }
+
+        let radix = match (self.peek(), self.peek2()) {
+            (Some('0'), Some('x')) => 16,
+            (Some('0'), Some('b')) => 2,
+            (Some('0'), Some('o')) => 8,
+            _ => 10,
+        };
+        // `0b` etc. is only a prefix if there are digits after it, otherwise
+        // we lex `0` and leave the rest for the next token.
+        if radix != 10 && self.peek_nth(2).map_or(false, |c| c.is_digit(radix)) {
+            let sign = self.pos - start;
+            self.bump();
+            self.bump();
+            self.eat_digits(radix);
+            let text = self.src_from(start);
+            let digits = format!("{}{}", &text[..sign], text[sign + 2..].replace('_', ""));
+            return i64::from_str_radix(&digits, radix)
+                .map(TokenKind::Number)
+                .map_err(|_| LexErrorKind::NumberOverflow(text.to_owned()));
+        }
+
+        self.eat_digits(10);
+        let mut is_float = false;
+        if self.peek_byte() == Some(b'.') && self.peek2().map_or(false, |c| c.is_digit(10)) {
+            self.bump();
+            self.eat_digits(10);
+            is_float = true;
+        }
+        if self.peek_byte() == Some(b'e') || self.peek_byte() == Some(b'E') {
+            // Only an exponent if there are digits (and maybe a sign) next.
+            let mut next = self.peek_nth(1);
+            let signed = next == Some('+') || next == Some('-');
+            if signed {
+                next = self.peek_nth(2);
+            }
+            if next.map_or(false, |c| c.is_digit(10)) {
+                self.bump();
+                if signed {
+                    self.bump();
+                }
+                self.eat_digits(10);
+                is_float = true;
+            }
+        }
+
+        // Only digits etc. are accepted above, so the only possible error is
+        // overflow. The text can be parsed as it is, unless it has `_`s.
+        let text = self.src_from(start);
+        let digits = if text.contains('_') {
+            Cow::Owned(text.replace('_', ""))
+        } else {
+            Cow::Borrowed(text)
+        };
+        let result = if is_float {
+            digits.parse::<f64>().ok().filter(|n| n.is_finite()).map(TokenKind::Float)
         } else {
-            Token::Name(Str(result))
+            digits.parse().ok().map(TokenKind::Number)
+        };
+        result.ok_or_else(|| LexErrorKind::NumberOverflow(text.to_owned()))
+    }
+
+    // Eats digits in the given radix and `_`s.
+    fn eat_digits(&mut self, radix: u32) {
+        let mut i = 0;
+        loop {
+            match self.byte_at(i) {
+                Some(b) if b == b'_' || (b as char).is_digit(radix) => i += 1,
+                _ => break,
+            }
         }
+        self.bump_bytes(i);
     }
+
+    // Current chars are #\; returns TokenKind::Char. The char is either a
+    // single char, a name from CHAR_NAMES, or `x` and a hex code.
+    fn lex_char(&mut self) -> Result<TokenKind, LexErrorKind> {
+        // eat #\
+        self.bump();
+        self.bump();
+
+        // The first char may be a delimiter (e.g., `#\(`), but anything after
+        // it is part of a name.
+        let first = match self.peek() {
+            Some(c) => c.len_utf8(),
+            None => return Err(LexErrorKind::InvalidCharLiteral(String::new())),
+        };
+        let end = self.name_end(first);
+        let lo = self.pos;
+        self.bump_bytes(end);
+        let text = self.src_from(lo);
+
+        let mut chars = text.chars();
+        let first = chars.next().unwrap();
+        if chars.next().is_none() {
+            return Ok(TokenKind::Char(first));
+        }
+        if let Some(&(_, c)) = CHAR_NAMES.iter().find(|&&(name, _)| name == text) {
+            return Ok(TokenKind::Char(c));
+        }
+        if first == 'x' {
+            let c = u32::from_str_radix(&text[1..], 16).ok().and_then(::std::char::from_u32);
+            if let Some(c) = c {
+                return Ok(TokenKind::Char(c));
+            }
+        }
+        Err(LexErrorKind::InvalidCharLiteral(text.to_owned()))
+    }
+
+    // Returns TokenKind::Keyword, TokenKind::Bool, or TokenKind::Name.
+    fn lex_keyword_or_name(&mut self) -> TokenKind {
+        let end = self.name_end(0);
+        let lo = self.pos;
+        self.bump_bytes(end);
+        let result = self.src_from(lo);
+
+        match result {
+            "#t" | "#true" => return TokenKind::Bool(true),
+            "#f" | "#false" => return TokenKind::Bool(false),
+            _ => {}
+        }
+        match forms::lookup(result) {
+            Some(form) => TokenKind::Keyword(form.name()),
+            None => TokenKind::Name(Symbol::intern(result)),
+        }
+    }
+}
+
+// Returns s as a string literal which lexes back to s, i.e., quoted and with
+// special characters escaped.
+pub fn str_literal(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            '\0' => result.push_str("\\0"),
+            c if c.is_control() => result.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+// Names for chars in char literals, e.g., `#\space`.
+const CHAR_NAMES: [(&'static str, char); 9] = [
+    ("alarm", '\u{7}'),
+    ("backspace", '\u{8}'),
+    ("delete", '\u{7f}'),
+    ("escape", '\u{1b}'),
+    ("newline", '\n'),
+    ("null", '\0'),
+    ("return", '\r'),
+    ("space", ' '),
+    ("tab", '\t'),
+];
+
+// Returns c as a char literal which lexes back to c.
+pub fn char_literal(c: char) -> String {
+    if let Some(&(name, _)) = CHAR_NAMES.iter().find(|&&(_, n)| n == c) {
+        return format!("#\\{}", name);
+    }
+    if c.is_whitespace() || c.is_control() {
+        return format!("#\\x{:x}", c as u32);
+    }
+    format!("#\\{}", c)
+}
+
+// Chars which end a name.
+fn is_delimiter(c: char) -> bool {
+    match c {
+        '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';' | '\'' | '`' | ',' => true,
+        _ => false,
+    }
+}
+
+// Control characters (other than whitespace) may only appear inside string
+// literals.
+fn is_disallowed(c: char) -> bool {
+    c.is_control() && !c.is_whitespace()
 }
 
 
@@ -170,105 +1169,477 @@ impl<'a> Lexer<'a> {
 mod test {
     use super::*;
 
+    fn kinds(input: &str) -> Vec<LexErrorKind> {
+        super::lex(input).unwrap_err().into_iter().map(|e| e.kind).collect()
+    }
+
+    fn lex(input: &str) -> Vec<Token> {
+        super::lex(input).unwrap()
+    }
+
     #[test]
     fn test_empty() {
-        assert!(lex("") == vec![]);
+        assert!(lex("").is_empty());
     }
 
     #[test]
     fn test_single() {
-        assert!(lex("(") == vec![Token::Bra]);
-        assert!(lex(")") == vec![Token::Ket]);
-        assert!(lex("let") == vec![Token::Keyword("let")]);
-        assert!(lex("print") == vec![Token::Keyword("print")]);
-        assert!(lex("+") == vec![Token::Keyword("+")]);
-        assert!(lex("\"\"") == vec![Token::Str(Str::new(""))]);
-        assert!(lex("\"foo\"") == vec![Token::Str(Str::new("foo"))]);
-        assert!(lex("\"foo + 3 + bar\"") == vec![Token::Str(Str::new("foo + 3 + bar"))]);
-        assert!(lex("0") == vec![Token::Number(0)]);
-        assert!(lex("1") == vec![Token::Number(1)]);
-        assert!(lex("42") == vec![Token::Number(42)]);
-        assert!(lex("foo") == vec![Token::Name(Str::new("foo"))]);
-        assert!(lex("FOO") == vec![Token::Name(Str::new("FOO"))]);
-        assert!(lex("Bar") == vec![Token::Name(Str::new("Bar"))]);
-        assert!(lex("qux42") == vec![Token::Name(Str::new("qux42"))]);
+        assert!(lex("(") == vec![TokenKind::Bra]);
+        assert!(lex(")") == vec![TokenKind::Ket]);
+        assert!(lex("let") == vec![TokenKind::Keyword("let")]);
+        assert!(lex("print") == vec![TokenKind::Keyword("print")]);
+        assert!(lex("+") == vec![TokenKind::Keyword("+")]);
+        assert!(lex("\"\"") == vec![TokenKind::Str(Str::new(""))]);
+        assert!(lex("\"foo\"") == vec![TokenKind::Str(Str::new("foo"))]);
+        assert!(lex("\"foo + 3 + bar\"") == vec![TokenKind::Str(Str::new("foo + 3 + bar"))]);
+        assert!(lex("0") == vec![TokenKind::Number(0)]);
+        assert!(lex("1") == vec![TokenKind::Number(1)]);
+        assert!(lex("42") == vec![TokenKind::Number(42)]);
+        assert!(lex("foo") == vec![TokenKind::Name(Symbol::intern("foo"))]);
+        assert!(lex("FOO") == vec![TokenKind::Name(Symbol::intern("FOO"))]);
+        assert!(lex("Bar") == vec![TokenKind::Name(Symbol::intern("Bar"))]);
+        assert!(lex("qux42") == vec![TokenKind::Name(Symbol::intern("qux42"))]);
     }
 
     #[test]
     fn test_single_ws() {
-        assert!(lex("    \n  ") == vec![]);
-        assert!(lex(" (") == vec![Token::Bra]);
-        assert!(lex(") ") == vec![Token::Ket]);
-        assert!(lex("let  ") == vec![Token::Keyword("let")]);
-        assert!(lex("print\n") == vec![Token::Keyword("print")]);
-        assert!(lex(" \n   +") == vec![Token::Keyword("+")]);
-        assert!(lex("\n\n\"\"") == vec![Token::Str(Str::new(""))]);
-        assert!(lex(" \"foo\" ") == vec![Token::Str(Str::new("foo"))]);
-        assert!(lex("     \"foo + 3 + bar\"\n  \n ") == vec![Token::Str(Str::new("foo + 3 + bar"))]);
+        assert!(lex("    \n  ").is_empty());
+        assert!(lex(" (") == vec![TokenKind::Bra]);
+        assert!(lex(") ") == vec![TokenKind::Ket]);
+        assert!(lex("let  ") == vec![TokenKind::Keyword("let")]);
+        assert!(lex("print\n") == vec![TokenKind::Keyword("print")]);
+        assert!(lex(" \n   +") == vec![TokenKind::Keyword("+")]);
+        assert!(lex("\n\n\"\"") == vec![TokenKind::Str(Str::new(""))]);
+        assert!(lex(" \"foo\" ") == vec![TokenKind::Str(Str::new("foo"))]);
+        assert!(lex("     \"foo + 3 + bar\"\n  \n ") == vec![TokenKind::Str(Str::new("foo + 3 + bar"))]);
     }
 
     #[test]
     fn test_two() {
-        assert!(lex("()") == vec![Token::Bra,Token::Ket]);
-        assert!(lex("))") == vec![Token::Ket,Token::Ket]);
-        assert!(lex("let(") == vec![Token::Keyword("let"), Token::Bra]);
-        assert!(lex("print)") == vec![Token::Keyword("print"),Token::Ket]);
-        assert!(lex("foo\"\"") == vec![Token::Name(Str::new("foo")),Token::Str(Str::new(""))]);
-        assert!(lex("\"foo\"foo") == vec![Token::Str(Str::new("foo")), Token::Name(Str::new("foo"))]);
-        assert!(lex("\"foo + 3 + bar\"+") == vec![Token::Str(Str::new("foo + 3 + bar")), Token::Keyword("+")]);
-        assert!(lex("0foo") == vec![Token::Number(0),Token::Name(Str::new("foo"))]);
+        assert!(lex("()") == vec![TokenKind::Bra,TokenKind::Ket]);
+        assert!(lex("))") == vec![TokenKind::Ket,TokenKind::Ket]);
+        assert!(lex("let(") == vec![TokenKind::Keyword("let"), TokenKind::Bra]);
+        assert!(lex("print)") == vec![TokenKind::Keyword("print"),TokenKind::Ket]);
+        assert!(lex("foo\"\"") == vec![TokenKind::Name(Symbol::intern("foo")),TokenKind::Str(Str::new(""))]);
+        assert!(lex("\"foo\"foo") == vec![TokenKind::Str(Str::new("foo")), TokenKind::Name(Symbol::intern("foo"))]);
+        assert!(lex("\"foo + 3 + bar\"+") == vec![TokenKind::Str(Str::new("foo + 3 + bar")), TokenKind::Keyword("+")]);
+        assert!(lex("0foo") == vec![TokenKind::Number(0),TokenKind::Name(Symbol::intern("foo"))]);
     }
 
     #[test]
     fn test_two_ws() {
-        assert!(lex("( )") == vec![Token::Bra,Token::Ket]);
-        assert!(lex(") )") == vec![Token::Ket,Token::Ket]);
-        assert!(lex("let\n(") == vec![Token::Keyword("let"), Token::Bra]);
-        assert!(lex("+\n+") == vec![Token::Keyword("+"), Token::Keyword("+")]);
-        assert!(lex("print      )") == vec![Token::Keyword("print"),Token::Ket]);
-        assert!(lex("foo \"\"") == vec![Token::Name(Str::new("foo")),Token::Str(Str::new(""))]);
-        assert!(lex("\"foo\" foo") == vec![Token::Str(Str::new("foo")), Token::Name(Str::new("foo"))]);
-        assert!(lex("\"foo + 3 + bar\" +") == vec![Token::Str(Str::new("foo + 3 + bar")), Token::Keyword("+")]);
-        assert!(lex("0\n\nfoo") == vec![Token::Number(0),Token::Name(Str::new("foo"))]);
-        assert!(lex("foo 42") == vec![Token::Name(Str::new("foo")), Token::Number(42)]);
+        assert!(lex("( )") == vec![TokenKind::Bra,TokenKind::Ket]);
+        assert!(lex(") )") == vec![TokenKind::Ket,TokenKind::Ket]);
+        assert!(lex("let\n(") == vec![TokenKind::Keyword("let"), TokenKind::Bra]);
+        assert!(lex("+\n+") == vec![TokenKind::Keyword("+"), TokenKind::Keyword("+")]);
+        assert!(lex("print      )") == vec![TokenKind::Keyword("print"),TokenKind::Ket]);
+        assert!(lex("foo \"\"") == vec![TokenKind::Name(Symbol::intern("foo")),TokenKind::Str(Str::new(""))]);
+        assert!(lex("\"foo\" foo") == vec![TokenKind::Str(Str::new("foo")), TokenKind::Name(Symbol::intern("foo"))]);
+        assert!(lex("\"foo + 3 + bar\" +") == vec![TokenKind::Str(Str::new("foo + 3 + bar")), TokenKind::Keyword("+")]);
+        assert!(lex("0\n\nfoo") == vec![TokenKind::Number(0),TokenKind::Name(Symbol::intern("foo"))]);
+        assert!(lex("foo 42") == vec![TokenKind::Name(Symbol::intern("foo")), TokenKind::Number(42)]);
     }
 
     #[test]
     fn test_realistic() {
-        assert!(lex("(print \"Hello world!\")") == vec![Token::Bra,
-                                                        Token::Keyword("print"),
-                                                        Token::Str(Str::new("Hello world!")),
-                                                        Token::Ket]);
-        assert!(lex("a (let a 42 (fn x (+ x a)))") == vec![Token::Name(Str::new("a")),
-                                                           Token::Bra,
-                                                           Token::Keyword("let"),
-                                                           Token::Name(Str::new("a")),
-                                                           Token::Number(42),
-                                                           Token::Bra,
-                                                           Token::Keyword("fn"),
-                                                           Token::Name(Str::new("x")),
-                                                           Token::Bra,
-                                                           Token::Keyword("+"),
-                                                           Token::Name(Str::new("x")),
-                                                           Token::Name(Str::new("a")),
-                                                           Token::Ket,
-                                                           Token::Ket,
-                                                           Token::Ket]);
-        assert!(lex("((fn x (+ x 42)) (+ 3 \"a string\"))") == vec![Token::Bra,
-                                                                    Token::Bra,
-                                                                    Token::Keyword("fn"),
-                                                                    Token::Name(Str::new("x")),
-                                                                    Token::Bra,
-                                                                    Token::Keyword("+"),
-                                                                    Token::Name(Str::new("x")),
-                                                                    Token::Number(42),
-                                                                    Token::Ket,
-                                                                    Token::Ket,
-                                                                    Token::Bra,
-                                                                    Token::Keyword("+"),
-                                                                    Token::Number(3),
-                                                                    Token::Str(Str::new("a string")),
-                                                                    Token::Ket,
-                                                                    Token::Ket]);
+        assert!(lex("(print \"Hello world!\")") == vec![TokenKind::Bra,
+                                                        TokenKind::Keyword("print"),
+                                                        TokenKind::Str(Str::new("Hello world!")),
+                                                        TokenKind::Ket]);
+        assert!(lex("a (let a 42 (fn x (+ x a)))") == vec![TokenKind::Name(Symbol::intern("a")),
+                                                           TokenKind::Bra,
+                                                           TokenKind::Keyword("let"),
+                                                           TokenKind::Name(Symbol::intern("a")),
+                                                           TokenKind::Number(42),
+                                                           TokenKind::Bra,
+                                                           TokenKind::Keyword("fn"),
+                                                           TokenKind::Name(Symbol::intern("x")),
+                                                           TokenKind::Bra,
+                                                           TokenKind::Keyword("+"),
+                                                           TokenKind::Name(Symbol::intern("x")),
+                                                           TokenKind::Name(Symbol::intern("a")),
+                                                           TokenKind::Ket,
+                                                           TokenKind::Ket,
+                                                           TokenKind::Ket]);
+        assert!(lex("((fn x (+ x 42)) (+ 3 \"a string\"))") == vec![TokenKind::Bra,
+                                                                    TokenKind::Bra,
+                                                                    TokenKind::Keyword("fn"),
+                                                                    TokenKind::Name(Symbol::intern("x")),
+                                                                    TokenKind::Bra,
+                                                                    TokenKind::Keyword("+"),
+                                                                    TokenKind::Name(Symbol::intern("x")),
+                                                                    TokenKind::Number(42),
+                                                                    TokenKind::Ket,
+                                                                    TokenKind::Ket,
+                                                                    TokenKind::Bra,
+                                                                    TokenKind::Keyword("+"),
+                                                                    TokenKind::Number(3),
+                                                                    TokenKind::Str(Str::new("a string")),
+                                                                    TokenKind::Ket,
+                                                                    TokenKind::Ket]);
+    }
+
+    #[test]
+    fn test_spans() {
+        let toks = lex("(print\n  \"foo\" 42)");
+        let spans: Vec<_> = toks.iter().map(|t| t.span).collect();
+        assert!(spans == vec![Span::new(0, 1, 1, 1),
+                              Span::new(1, 6, 1, 2),
+                              Span::new(9, 14, 2, 3),
+                              Span::new(15, 17, 2, 9),
+                              Span::new(17, 18, 2, 11)]);
+        // Columns count chars, offsets count bytes.
+        let toks = lex("\"é\" x");
+        assert!(toks[0].span == Span::new(0, 4, 1, 1));
+        assert!(toks[1].span == Span::new(5, 6, 1, 5));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(super::lex("\"foo") ==
+                Err(vec![LexError { kind: LexErrorKind::UnterminatedString, span: Span::new(0, 4, 1, 1) }]));
+        assert!(super::lex("(+ 1 99999999999999999999)") ==
+                Err(vec![LexError { kind: LexErrorKind::NumberOverflow("99999999999999999999".to_owned()),
+                                    span: Span::new(5, 25, 1, 6) }]));
+        assert!(super::lex("4294967295").is_ok());
+        assert!(super::lex("foo\u{0}bar") ==
+                Err(vec![LexError { kind: LexErrorKind::DisallowedChar('\u{0}'), span: Span::new(3, 4, 1, 4) }]));
+        // Control chars are fine in strings.
+        assert!(super::lex("\"\u{7}\"").is_ok());
+        // All errors are reported.
+        let errs = super::lex("a \u{1b} 9223372036854775808 \"b").unwrap_err();
+        assert!(errs.iter().map(|e| e.kind.clone()).collect::<Vec<_>>() ==
+                vec![LexErrorKind::DisallowedChar('\u{1b}'),
+                     LexErrorKind::NumberOverflow("9223372036854775808".to_owned()),
+                     LexErrorKind::UnterminatedString]);
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert!(lex(r#""a\"b""#) == vec![TokenKind::Str(Str::new("a\"b"))]);
+        assert!(lex(r#""a\\b""#) == vec![TokenKind::Str(Str::new("a\\b"))]);
+        assert!(lex(r#""a\nb\tc\r\0""#) == vec![TokenKind::Str(Str::new("a\nb\tc\r\0"))]);
+        assert!(lex(r#""\u{41}\u{e9}\u{1F600}""#) == vec![TokenKind::Str(Str::new("Aé😀"))]);
+        assert!(lex(r#""\\" x"#) == vec![TokenKind::Str(Str::new("\\")), TokenKind::Name(Symbol::intern("x"))]);
+        // The span covers the whole literal, including escapes.
+        assert!(lex(r#""\n" x"#)[1].span == Span::new(5, 6, 1, 6));
+    }
+
+    #[test]
+    fn test_escape_errors() {
+        assert!(super::lex(r#""a\qb""#) ==
+                Err(vec![LexError { kind: LexErrorKind::UnknownEscape('q'), span: Span::new(2, 4, 1, 3) }]));
+        assert!(kinds(r#""\u41""#) == vec![LexErrorKind::InvalidUnicodeEscape]);
+        assert!(kinds(r#""\u{}""#) == vec![LexErrorKind::InvalidUnicodeEscape]);
+        assert!(kinds(r#""\u{1234567}""#) == vec![LexErrorKind::InvalidUnicodeEscape]);
+        assert!(kinds(r#""\u{d800}""#) == vec![LexErrorKind::InvalidUnicodeEscape]);
+        assert!(kinds(r#""\u{zz}""#) == vec![LexErrorKind::InvalidUnicodeEscape]);
+        assert!(kinds(r#""a\"#) == vec![LexErrorKind::UnterminatedString]);
+        // Errors in escapes don't stop lexing.
+        assert!(kinds(r#""\q\w" "#) == vec![LexErrorKind::UnknownEscape('q'), LexErrorKind::UnknownEscape('w')]);
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        assert!(lex(r#"r"a\nb""#) == vec![TokenKind::Str(Str::new("a\\nb"))]);
+        assert!(lex("r\"line 1\nline 2\"") == vec![TokenKind::Str(Str::new("line 1\nline 2"))]);
+        assert!(lex(r###"r#"{"a": "b"}"#"###) == vec![TokenKind::Str(Str::new(r#"{"a": "b"}"#))]);
+        assert!(lex(r###"r##"a"#b"##"###) == vec![TokenKind::Str(Str::new(r##"a"#b"##))]);
+        assert!(lex(r#"r"" r"#) == vec![TokenKind::Str(Str::new("")), TokenKind::Name(Symbol::intern("r"))]);
+        // Not raw strings.
+        assert!(lex("r#a") == vec![TokenKind::Name(Symbol::intern("r#a"))]);
+        assert!(lex("bar") == vec![TokenKind::Name(Symbol::intern("bar"))]);
+        assert!(super::lex(r###"r#"a""###).unwrap_err()[0].kind == LexErrorKind::UnterminatedString);
+    }
+
+    #[test]
+    fn test_str_literal() {
+        assert!(str_literal("foo") == r#""foo""#);
+        assert!(str_literal("a\"b\\c\nd\te\u{1}") == r#""a\"b\\c\nd\te\u{1}""#);
+        for s in &["", "a\"b", "\\", "\n\t\r\0", "é😀", "\u{7f}"] {
+            assert!(lex(&str_literal(s)) == vec![TokenKind::Str(Str::new(s))]);
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        assert!(lex("; just a comment").is_empty());
+        assert!(lex("a ; comment\nb") == vec![TokenKind::Name(Symbol::intern("a")), TokenKind::Name(Symbol::intern("b"))]);
+        assert!(lex("(a;comment)\n)") == vec![TokenKind::Bra, TokenKind::Name(Symbol::intern("a")), TokenKind::Ket]);
+        assert!(lex("\"a ; b\"") == vec![TokenKind::Str(Str::new("a ; b"))]);
+        assert!(lex("a #| block\ncomment |# b") == vec![TokenKind::Name(Symbol::intern("a")), TokenKind::Name(Symbol::intern("b"))]);
+        assert!(lex("#| outer #| inner |# still outer |#42") == vec![TokenKind::Number(42)]);
+        assert!(lex("#|a|#|#") == vec![TokenKind::Name(Symbol::intern("|#"))]);
+        assert!(lex("a #; b c") == vec![TokenKind::Name(Symbol::intern("a")), TokenKind::Name(Symbol::intern("c"))]);
+        assert!(lex("(#;(x (y)) z)") == vec![TokenKind::Bra, TokenKind::Name(Symbol::intern("z")), TokenKind::Ket]);
+        assert!(lex("#; ; line\n #| block |# x y") == vec![TokenKind::Name(Symbol::intern("y"))]);
+        assert!(lex("#; #; a b c") == vec![TokenKind::Name(Symbol::intern("c"))]);
+        assert!(lex("#foo") == vec![TokenKind::Name(Symbol::intern("#foo"))]);
+        // Spans of following tokens are unaffected.
+        assert!(lex("#| é |# x")[0].span == Span::new(9, 10, 1, 9));
+    }
+
+    #[test]
+    fn test_keep_comments() {
+        let toks = lex_with_comments("a ; one\n#| two |# #;(three 3) b").unwrap();
+        assert!(toks == vec![TokenKind::Name(Symbol::intern("a")),
+                             TokenKind::Comment(Str::new("; one")),
+                             TokenKind::Comment(Str::new("#| two |#")),
+                             TokenKind::Comment(Str::new("#;(three 3)")),
+                             TokenKind::Name(Symbol::intern("b"))]);
+        assert!(toks[3].span == Span::new(18, 29, 2, 11));
+    }
+
+    #[test]
+    fn test_comment_errors() {
+        assert!(kinds("#| a") == vec![LexErrorKind::UnterminatedComment]);
+        assert!(kinds("#| a #| b |#") == vec![LexErrorKind::UnterminatedComment]);
+        assert!(kinds("#; (a b") == vec![LexErrorKind::UnterminatedComment]);
+        assert!(kinds("#;") == vec![LexErrorKind::MissingDatum]);
+        assert!(kinds("(a #;)") == vec![LexErrorKind::MissingDatum]);
+        assert!(super::lex("x #;").unwrap_err()[0].span == Span::new(2, 4, 1, 3));
+    }
+
+    #[test]
+    fn test_lossless() {
+        let input = " (a\t;c\n  #|b|# \"\\n\" #;x 42)\r\n";
+        let toks = lex_lossless(input).unwrap();
+        assert!(toks == vec![TokenKind::Whitespace(Str::new(" ")),
+                             TokenKind::Bra,
+                             TokenKind::Name(Symbol::intern("a")),
+                             TokenKind::Whitespace(Str::new("\t")),
+                             TokenKind::Comment(Str::new(";c")),
+                             TokenKind::Whitespace(Str::new("\n  ")),
+                             TokenKind::Comment(Str::new("#|b|#")),
+                             TokenKind::Whitespace(Str::new(" ")),
+                             TokenKind::Str(Str::new("\n")),
+                             TokenKind::Whitespace(Str::new(" ")),
+                             TokenKind::Comment(Str::new("#;x")),
+                             TokenKind::Whitespace(Str::new(" ")),
+                             TokenKind::Number(42),
+                             TokenKind::Ket,
+                             TokenKind::Whitespace(Str::new("\r\n"))]);
+        // No gaps.
+        let mut pos = 0;
+        for t in &toks {
+            assert!(t.span.lo == pos);
+            pos = t.span.hi;
+        }
+        assert!(pos == input.len());
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert!(lex("-5") == vec![TokenKind::Number(-5)]);
+        assert!(lex("(foo -42)") == vec![TokenKind::Bra, TokenKind::Name(Symbol::intern("foo")), TokenKind::Number(-42), TokenKind::Ket]);
+        assert!(lex("- -x x-1") == vec![TokenKind::Name(Symbol::intern("-")),
+                                        TokenKind::Name(Symbol::intern("-x")),
+                                        TokenKind::Name(Symbol::intern("x-1"))]);
+        assert!(lex("1_000_000") == vec![TokenKind::Number(1000000)]);
+        assert!(lex("0xff 0xFF_FF -0x10") == vec![TokenKind::Number(255), TokenKind::Number(0xffff), TokenKind::Number(-16)]);
+        assert!(lex("0b1010 0o777") == vec![TokenKind::Number(10), TokenKind::Number(0o777)]);
+        assert!(lex("9223372036854775807 -9223372036854775808") ==
+                vec![TokenKind::Number(9223372036854775807), TokenKind::Number(-9223372036854775808)]);
+        // Not prefixes.
+        assert!(lex("0xg 0b2") == vec![TokenKind::Number(0), TokenKind::Name(Symbol::intern("xg")),
+                                       TokenKind::Number(0), TokenKind::Name(Symbol::intern("b2"))]);
+    }
+
+    #[test]
+    fn test_floats() {
+        assert!(lex("1.5") == vec![TokenKind::Float(1.5)]);
+        assert!(lex("-0.25") == vec![TokenKind::Float(-0.25)]);
+        assert!(lex("1e3 1E-3 2.5e+2") == vec![TokenKind::Float(1e3), TokenKind::Float(1e-3), TokenKind::Float(2.5e2)]);
+        assert!(lex("1_000.000_1") == vec![TokenKind::Float(1000.0001)]);
+        // Not floats.
+        assert!(lex("1.x") == vec![TokenKind::Number(1), TokenKind::Name(Symbol::intern(".x"))]);
+        assert!(lex("2e") == vec![TokenKind::Number(2), TokenKind::Name(Symbol::intern("e"))]);
+        assert!(lex("2e+") == vec![TokenKind::Number(2), TokenKind::Name(Symbol::intern("e+"))]);
+        // Display gives something which lexes back to the same thing.
+        for f in &[1.0, -0.5, 1e300, 1.5e-10] {
+            let t = TokenKind::Float(*f);
+            assert!(lex(&t.to_string()) == vec![t]);
+        }
+    }
+
+    #[test]
+    fn test_number_errors() {
+        assert!(kinds("0x8000000000000000") == vec![LexErrorKind::NumberOverflow("0x8000000000000000".to_owned())]);
+        assert!(kinds("1e999") == vec![LexErrorKind::NumberOverflow("1e999".to_owned())]);
+        assert!(super::lex("-0x8000000000000000").is_ok());
+    }
+
+    #[test]
+    fn test_quotes() {
+        assert!(lex("'x") == vec![TokenKind::Quote, TokenKind::Name(Symbol::intern("x"))]);
+        assert!(lex("`(a ,b ,@c)") == vec![TokenKind::Quasiquote,
+                                           TokenKind::Bra,
+                                           TokenKind::Name(Symbol::intern("a")),
+                                           TokenKind::Unquote,
+                                           TokenKind::Name(Symbol::intern("b")),
+                                           TokenKind::UnquoteSplicing,
+                                           TokenKind::Name(Symbol::intern("c")),
+                                           TokenKind::Ket]);
+        assert!(lex("a'b") == vec![TokenKind::Name(Symbol::intern("a")), TokenKind::Quote, TokenKind::Name(Symbol::intern("b"))]);
+        assert!(lex("', @") == vec![TokenKind::Quote, TokenKind::Unquote, TokenKind::Name(Symbol::intern("@"))]);
+        assert!(lex("\"'`,\"") == vec![TokenKind::Str(Str::new("'`,"))]);
+        assert!(lex("#;'(a b) c") == vec![TokenKind::Name(Symbol::intern("c"))]);
+        assert!(lex("#;`,@a c") == vec![TokenKind::Name(Symbol::intern("c"))]);
+        assert!(kinds("(#;')") == vec![LexErrorKind::MissingDatum]);
+    }
+
+    // A reader which returns its chunks one read at a time, and then fails.
+    struct Chunks(Vec<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::Other, "no more chunks"));
+            }
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn test_reader() {
+        let input = "; comment\n(let x 42 (+ x \"\u{e9}\u{1F600}\" 'r#\"raw\"# -1.5e3))\n";
+        let toks: Result<Vec<_>, _> = Lexer::from_reader(input.as_bytes()).collect();
+        let toks = toks.unwrap();
+        assert!(toks == lex(input));
+        let spans: Vec<_> = toks.iter().map(|t| t.span).collect();
+        let expected: Vec<_> = lex(input).iter().map(|t| t.span).collect();
+        assert!(spans == expected);
+
+        // Errors are returned in order, along with the tokens.
+        let result: Vec<_> = Lexer::new("a \"\\q\" \u{7}").collect();
+        assert!(result.len() == 4);
+        assert!(result[0].as_ref().unwrap().kind == TokenKind::Name(Symbol::intern("a")));
+        assert!(result[1].as_ref().unwrap_err().kind == LexErrorKind::UnknownEscape('q'));
+        assert!(result[2].as_ref().unwrap().kind == TokenKind::Str(Str::new("")));
+        assert!(result[3].as_ref().unwrap_err().kind == LexErrorKind::DisallowedChar('\u{7}'));
+    }
+
+    #[test]
+    fn test_reader_lazy() {
+        // Tokens are returned without reading past the end of the token, so
+        // we never get to the failing read.
+        let mut lexer = Lexer::from_reader(Chunks(vec![b"(a b", b"c)", b"  ("]));
+        for _ in 0..4 {
+            assert!(lexer.next().unwrap().is_ok());
+        }
+        assert!(lexer.next().unwrap().unwrap().kind == TokenKind::Bra);
+        match lexer.next().unwrap().unwrap_err().kind {
+            LexErrorKind::Io(ref s) => assert!(s == "no more chunks"),
+            ref k => panic!("unexpected error: {:?}", k),
+        }
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn test_reader_utf8() {
+        // Lexing stops at invalid UTF-8, the error is found while looking for
+        // the end of `ab`.
+        let result: Vec<_> = Lexer::from_reader(&b"ab\xff cd"[..]).collect();
+        assert!(result.len() == 2);
+        match result[0].as_ref().unwrap_err().kind {
+            LexErrorKind::Io(_) => {}
+            ref k => panic!("unexpected error: {:?}", k),
+        }
+        assert!(result[1].as_ref().unwrap().kind == TokenKind::Name(Symbol::intern("ab")));
+        // A char split across reads.
+        let mut lexer = Lexer::from_reader(Chunks(vec![b"\"\xc3", b"\xa9\""]));
+        assert!(lexer.next().unwrap().unwrap().kind == TokenKind::Str(Str::new("\u{e9}")));
+        assert!(lexer.next().unwrap().is_err());
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn test_collections() {
+        assert!(lex("[a]{b}") == vec![TokenKind::SquareBra,
+                                      TokenKind::Name(Symbol::intern("a")),
+                                      TokenKind::SquareKet,
+                                      TokenKind::CurlyBra,
+                                      TokenKind::Name(Symbol::intern("b")),
+                                      TokenKind::CurlyKet]);
+        assert!(lex("#;[a (b)] #;{c d} e") == vec![TokenKind::Name(Symbol::intern("e"))]);
+        assert!(kinds("[#;]") == vec![LexErrorKind::MissingDatum]);
+    }
+
+    #[test]
+    fn test_bools() {
+        assert!(lex("#t #f #true #false") == vec![TokenKind::Bool(true),
+                                                  TokenKind::Bool(false),
+                                                  TokenKind::Bool(true),
+                                                  TokenKind::Bool(false)]);
+        assert!(lex("(#t)") == vec![TokenKind::Bra, TokenKind::Bool(true), TokenKind::Ket]);
+        assert!(lex("#tx") == vec![TokenKind::Name(Symbol::intern("#tx"))]);
+        assert!(TokenKind::Bool(false).to_string() == "#f");
+    }
+
+    #[test]
+    fn test_chars() {
+        assert!(lex("#\\a #\\( #\\\u{e9} #\\space #\\x3bb #\\x") == vec![TokenKind::Char('a'),
+                                                                   TokenKind::Char('('),
+                                                                   TokenKind::Char('\u{e9}'),
+                                                                   TokenKind::Char(' '),
+                                                                   TokenKind::Char('\u{3bb}'),
+                                                                   TokenKind::Char('x')]);
+        assert!(lex("(#\\))") == vec![TokenKind::Bra, TokenKind::Char(')'), TokenKind::Ket]);
+        assert!(kinds("#\\") == vec![LexErrorKind::InvalidCharLiteral("".to_owned())]);
+        assert!(kinds("#\\spaces #\\xd800") == vec![LexErrorKind::InvalidCharLiteral("spaces".to_owned()),
+                                                    LexErrorKind::InvalidCharLiteral("xd800".to_owned())]);
+
+        for &c in &['a', '(', '"', '\\', ' ', '\n', '\u{7f}', '\u{1}', '\u{a0}', '\u{3bb}'] {
+            assert!(lex(&char_literal(c)) == vec![TokenKind::Char(c)]);
+        }
+        assert!(char_literal('\n') == "#\\newline");
+        assert!(char_literal('\u{1}') == "#\\x1");
+    }
+
+    #[test]
+    fn test_json() {
+        let toks = lex_with_comments("(let [x] \"s\" 4.5 #\\a) ; c").unwrap();
+        let json = ::serde_json::to_string(&toks).unwrap();
+        assert!(json.starts_with(r#"[{"kind":"Bra","span":{"lo":0,"hi":1,"line":1,"col":1}},{"kind":"Keyword","value":"let","span":"#));
+        let back: Vec<Token> = ::serde_json::from_str(&json).unwrap();
+        assert!(back == toks);
+        assert!(back.iter().zip(toks.iter()).all(|(a, b)| a.span == b.span));
+
+        let t: Token = ::serde_json::from_str(r#"{"kind":"Name","value":"x"}"#).unwrap();
+        assert!(t == TokenKind::Name(Symbol::intern("x")) && t.span == DUMMY_SP);
+        assert!(::serde_json::from_str::<Token>(r#"{"kind":"Keyword","value":"nope"}"#).is_err());
+    }
+
+    #[test]
+    fn test_reader_chunks() {
+        // However the input is split up, we get the same tokens and errors as
+        // lexing all of it at once.
+        let input = "; \u{e9}\n(let x_1 -0x2a_ff (f \"a\\n\\q\u{1F600}\" r##\"r\"#\"## #| a |# #;(b) 1.5e3))\n\
+                     \u{a0}[#t #\\space #\\\u{3bb} {'k `(v ,@w)}] 12e999 \"unterminated";
+        let mut lexer = Lexer::new(input);
+        lexer.keep_comments = true;
+        let expected: Vec<_> = lexer.collect();
+        assert!(expected.iter().filter(|t| t.is_err()).count() == 3);
+        for &size in &[1, 2, 3, 5, 8] {
+            let chunks = input.as_bytes().chunks(size).collect();
+            let mut lexer = Lexer::from_reader(Chunks(chunks));
+            lexer.keep_comments = true;
+            // The reader fails after the last chunk.
+            let (io, result): (Vec<_>, Vec<_>) = lexer.partition(|t| match *t {
+                Err(LexError { kind: LexErrorKind::Io(_), .. }) => true,
+                _ => false,
+            });
+            assert!(io.len() == 1);
+            assert!(result == expected);
+            for (r, e) in result.iter().zip(expected.iter()) {
+                match (r, e) {
+                    (&Ok(ref r), &Ok(ref e)) => assert!(r.span == e.span),
+                    (&Err(ref r), &Err(ref e)) => assert!(r.span == e.span),
+                    _ => unreachable!(),
+                }
+            }
+        }
     }
 }
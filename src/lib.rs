@@ -0,0 +1,26 @@
+#![feature(rustc_private)]
+
+pub mod symbol;
+pub mod lexer;
+#[macro_use]
+pub mod parser;
+pub mod visit;
+pub mod expand;
+pub mod forms;
+pub mod check;
+pub mod cst;
+pub mod pretty;
+pub mod data;
+pub mod dot;
+pub mod search;
+pub mod rewrite;
+pub mod interpreter;
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[cfg(test)]
+extern crate serde_json;
@@ -1,20 +1,70 @@
-#![feature(rustc_private)]
+// The command line interface, the language itself is in the library (lib.rs).
 
-mod lexer;
-#[macro_use]
-mod parser;
-mod expand;
-mod interpreter;
-
-#[macro_use]
-extern crate log;
+extern crate s;
+extern crate serde_json;
 
+use s::{lexer, parser, expand, check, cst, pretty, dot, search, rewrite, interpreter};
+use std::fmt::Display;
 use std::io::{Read, stdin};
 
-const KEYWORDS: [&'static str; 5] = ["+", "fn", "let", "macro", "print"];
+// Lexes input, or reports lexing errors and returns None.
+fn lex_or_report(input: &str) -> Option<Vec<lexer::Token>> {
+    report_lex_errors(lexer::lex(input))
+}
+
+fn report_lex_errors(result: Result<Vec<lexer::Token>, Vec<lexer::LexError>>) -> Option<Vec<lexer::Token>> {
+    match result {
+        Ok(toks) => Some(toks),
+        Err(errs) => {
+            report_errors(&errs);
+            None
+        }
+    }
+}
+
+fn report_errors<E: Display>(errs: &[E]) {
+    for e in errs {
+        println!("error: {}", e);
+    }
+}
+
+// Reports any problems found by the static checks, returns true if there were
+// none.
+fn check_or_report(ast: &parser::Node) -> bool {
+    let errs = check::check(ast);
+    report_errors(&errs);
+    errs.is_empty()
+}
 
-fn lex(input: &str) {
-    let toks = lexer::lex(input);
+// Lexes and parses input, or reports every error and returns None.
+fn parse_or_report(input: &str) -> Option<parser::Node> {
+    let toks = match lex_or_report(input) {
+        Some(toks) => toks,
+        None => return None,
+    };
+    let (ast, errs) = parser::parse_recovering(&toks);
+    if !errs.is_empty() {
+        report_errors(&errs);
+        return None;
+    }
+    Some(ast)
+}
+
+// If comments is true, comments are included in the output.
+fn lex(input: &str, comments: bool, json: bool) {
+    let result = if comments {
+        lexer::lex_with_comments(input)
+    } else {
+        lexer::lex(input)
+    };
+    let toks = match report_lex_errors(result) {
+        Some(toks) => toks,
+        None => return,
+    };
+    if json {
+        println!("{}", serde_json::to_string(&toks).unwrap());
+        return;
+    }
     for t in &toks {
         print!("{} ", t);
     }
@@ -22,23 +72,55 @@ fn lex(input: &str) {
     println!("{:?}", toks);
 }
 
-fn parse(input: &str) {
-    let toks = lexer::lex(input);
-    let ast = parser::parse(&toks);
+// The AST is printed even if there are syntax errors. If json is true, errors
+// go to stderr so that stdout is only JSON.
+fn parse(input: &str, json: bool) {
+    let toks = match lex_or_report(input) {
+        Some(toks) => toks,
+        None => return,
+    };
+    let (ast, errs) = parser::parse_recovering(&toks);
+    if json {
+        for e in &errs {
+            eprintln!("error: {}", e);
+        }
+        println!("{}", serde_json::to_string(&ast).unwrap());
+        return;
+    }
+    report_errors(&errs);
 
     println!("{:?}", ast);
 }
 
-fn print(input: &str) {
-    let toks = lexer::lex(input);
-    let ast = parser::parse(&toks);
+fn cst(input: &str) {
+    let file = match cst::parse(input) {
+        Ok(file) => file,
+        Err(errs) => {
+            report_errors(&errs);
+            return;
+        }
+    };
 
-    println!("{}", ast);
+    println!("{:?}", file);
+}
+
+fn print(input: &str, config: &pretty::Config) {
+    let ast = match parse_or_report(input) {
+        Some(ast) => ast,
+        None => return,
+    };
+
+    println!("{}", pretty::pretty(&ast, config));
 }
 
 fn unhygienic(input: &str) {
-    let toks = lexer::lex(input);
-    let ast = parser::parse(&toks);
+    let ast = match parse_or_report(input) {
+        Some(ast) => ast,
+        None => return,
+    };
+    if !check_or_report(&ast) {
+        return;
+    }
     let ast = expand::fold(ast, &mut expand::Unhygienic::new());
     println!("{}", ast);
     let result = interpreter::run_program(&ast);
@@ -46,12 +128,174 @@ fn unhygienic(input: &str) {
     println!("{:?}", result);    
 }
 
-fn run(input: &str) {
-    let toks = lexer::lex(input);
-    let ast = parser::parse(&toks);
-    let result = interpreter::run_program(&ast);
+// Prints the AST as a Graphviz graph. stage is which tree to show: the tree as
+// parsed (`before`), after macro expansion (`after`), or `both`. If
+// highlight is true, nodes in the expanded tree which come from a macro body
+// are filled in.
+fn dot(input: &str, stage: &str, highlight: bool) {
+    let ast = match parse_or_report(input) {
+        Some(ast) => ast,
+        None => return,
+    };
+    let bodies = if highlight { dot::macro_bodies(&ast) } else { vec![] };
+    let expanded = || {
+        if check_or_report(&ast) {
+            Some(expand::fold(ast.clone(), &mut expand::Unhygienic::new()))
+        } else {
+            None
+        }
+    };
+    let graph = match stage {
+        "before" => dot::dot(&[("before expansion", &ast, &[])]),
+        "after" => match expanded() {
+            Some(expanded) => dot::dot(&[("after expansion", &expanded, &bodies)]),
+            None => return,
+        },
+        "both" => match expanded() {
+            Some(expanded) => dot::dot(&[("before expansion", &ast, &[]), ("after expansion", &expanded, &bodies)]),
+            None => return,
+        },
+        s => {
+            println!("unknown stage: {} (expected before, after, or both)", s);
+            return;
+        }
+    };
+    print!("{}", graph);
+}
+
+// Prints each form in files which matches pattern (see search.rs), with its
+// position and what each metavariable matched. With no files, searches stdin.
+fn grep(pattern: &str, files: &[String]) {
+    let pattern = match search::Pattern::parse(pattern) {
+        Ok(pattern) => pattern,
+        Err(e) => {
+            println!("error: invalid pattern: {}", e);
+            return;
+        }
+    };
+    for (name, input) in read_inputs(files) {
+        let toks = match lexer::lex(&input) {
+            Ok(toks) => toks,
+            Err(errs) => {
+                report_file_errors(&name, &errs);
+                continue;
+            }
+        };
+        let ast = match parser::parse_recovering(&toks) {
+            (ast, ref errs) if errs.is_empty() => ast,
+            (_, errs) => {
+                report_file_errors(&name, &errs);
+                continue;
+            }
+        };
+        for m in pattern.search(&ast) {
+            println!("{}:{}: {}", name, m.node.span, m.node);
+            for &(var, ref n) in &m.bindings {
+                println!("    {} = {}", var, n);
+            }
+        }
+    }
+}
 
-    println!("{:?}", result);
+fn report_file_errors<E: Display>(name: &str, errs: &[E]) {
+    for e in errs {
+        println!("error: {}:{}", name, e);
+    }
+}
+
+// Applies the rules in the file rules (see rewrite.rs) to each file and prints
+// a diff of the changes, or if in_place is true, writes them to the file. With
+// no files, rewrites stdin.
+fn rewrite(rules: &str, files: &[String], in_place: bool) {
+    if in_place && files.is_empty() {
+        println!("--in-place needs files to rewrite");
+        return;
+    }
+    let rules = match std::fs::read_to_string(rules).map(|input| rewrite::parse_rules(&input)) {
+        Ok(Ok(rules)) => rules,
+        Ok(Err(e)) => return report_file_errors(rules, &[e]),
+        Err(e) => return println!("error: {}: {}", rules, e),
+    };
+    for (name, input) in read_inputs(files) {
+        let output = match rewrite::apply(&rules, &input) {
+            Ok(output) => output,
+            Err(e) => {
+                report_file_errors(&name, &[e]);
+                continue;
+            }
+        };
+        if !in_place {
+            print!("{}", rewrite::diff(&name, &input, &output));
+        } else if output != input {
+            if let Err(e) = std::fs::write(&name, output) {
+                println!("error: {}: {}", name, e);
+            }
+        }
+    }
+}
+
+// (name, contents) for each file, or for stdin if there are none. Files which
+// can't be read are reported and left out.
+fn read_inputs(files: &[String]) -> Vec<(String, String)> {
+    if files.is_empty() {
+        let mut input = String::new();
+        let result = stdin().read_to_string(&mut input);
+        assert!(result.is_ok(), "Reading stdin failed");
+        return vec![("<stdin>".to_owned(), input)];
+    }
+    let mut inputs = Vec::new();
+    for f in files {
+        match std::fs::read_to_string(f) {
+            Ok(input) => inputs.push((f.clone(), input)),
+            Err(e) => println!("error: {}: {}", f, e),
+        }
+    }
+    inputs
+}
+
+// Each top-level form is run (and its result printed) as soon as it has been
+// read.
+fn run<R: Read>(input: R) {
+    for form in parser::Parser::new(lexer::Lexer::from_reader(input)) {
+        match form {
+            Ok(form) => {
+                if !check_or_report(&form) {
+                    continue;
+                }
+                let result = interpreter::run_node(&form, &interpreter::Envr::new());
+                println!("{:?}", result);
+            }
+            Err(errs) => report_errors(&errs),
+        }
+    }
+}
+
+// Runs an AST which has been serialized as JSON (e.g., by `parse --format
+// json`), either a Program or a single form.
+fn run_json<R: Read>(input: R) {
+    let ast: parser::Node = match serde_json::from_reader(input) {
+        Ok(ast) => ast,
+        Err(e) => {
+            println!("error: invalid JSON: {}", e);
+            return;
+        }
+    };
+    if !check_or_report(&ast) {
+        return;
+    }
+    let forms = match ast.kind {
+        parser::NodeKind::Program(ns) => parser::unshare(ns),
+        _ => vec![ast],
+    };
+    for form in &forms {
+        let result = interpreter::run_node(form, &interpreter::Envr::new());
+        println!("{:?}", result);
+    }
+}
+
+// The value of the option `name`, i.e., the argument after it.
+fn option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).map(|a| &**a)
 }
 
 fn main() {
@@ -59,7 +303,42 @@ fn main() {
 
     if args.len() <= 1 {
         println!("no action provided");
-        println!("  usage: s [action]");
+        println!("  usage: s [action] [options]");
+        return;
+    }
+
+    let json = match option(&args, "--format") {
+        None | Some("text") => false,
+        Some("json") => true,
+        Some(f) => {
+            println!("unknown format: {}", f);
+            return;
+        }
+    };
+
+    // The other actions need all of the input before they can start.
+    if args[1] == "run" {
+        if json {
+            run_json(stdin());
+        } else {
+            run(stdin());
+        }
+        return;
+    }
+    // grep and rewrite read their input from files.
+    if args[1] == "grep" {
+        match args.get(2) {
+            Some(pattern) => grep(pattern, &args[3..]),
+            None => println!("  usage: s grep PATTERN [FILE ...]"),
+        }
+        return;
+    }
+    if args[1] == "rewrite" {
+        let files: Vec<_> = args.iter().skip(3).filter(|a| *a != "--in-place").cloned().collect();
+        match args.get(2) {
+            Some(rules) => rewrite(rules, &files, args[3..].iter().any(|a| a == "--in-place")),
+            None => println!("  usage: s rewrite RULES [FILE ...] [--in-place]"),
+        }
         return;
     }
 
@@ -68,11 +347,27 @@ fn main() {
     assert!(result.is_ok(), "Reading stdin failed");
 
     match &*args[1] {
-        "lex" => lex(&input),
-        "parse" => parse(&input),
-        "print" => print(&input),
-        "run" => run(&input),
+        "lex" => lex(&input, args[2..].iter().any(|a| a == "--comments"), json),
+        "parse" => parse(&input, json),
+        "cst" => cst(&input),
+        "print" => {
+            let mut config = pretty::Config::default();
+            if let Some(w) = option(&args, "--width") {
+                match w.parse() {
+                    Ok(w) => config.width = w,
+                    Err(_) => {
+                        println!("--width needs a number");
+                        return;
+                    }
+                }
+            }
+            print(&input, &config)
+        }
         "expand" => unhygienic(&input),
+        "dot" => {
+            let stage = option(&args, "--stage").unwrap_or("before");
+            dot(&input, stage, args[2..].iter().any(|a| a == "--highlight-macros"))
+        }
         a => println!("unknown action: {}", a),
     }
 }
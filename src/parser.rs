@@ -1,253 +1,1123 @@
-use lexer::{Token, Str};
+use lexer::{self, Token, TokenKind, LexError, LexErrorKind, Str, Span, DUMMY_SP};
+use forms;
+use symbol::Symbol;
+use std::collections::VecDeque;
 use std::fmt;
+use std::iter;
+use std::mem;
+use std::rc::Rc;
+use visit::{self, Visitor, VisitorMut};
 
 // AST
 // A Program is basically an s expression without parentheses, it only occurs at
 // the top level of the program. An expression may not be empty, it must start
 // with either a node, followed by any number of nodes, except keywords.
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum Node {
-    Program(Vec<Node>),
-    S(Vec<Node>),
-    Plus,
-    Fn,
-    Let,
-    Macro,
-    Print,
-    Ident(Str),
-    LitNum(u32),
+//
+// Children are reference counted, so cloning a node is cheap however big the
+// tree under it is, e.g., when a function value is looked up or a macro body is
+// copied into its expansion. Trees are never mutated once they are built (see
+// Node::push), so sharing is safe.
+//
+// See the README for how nodes are serialized.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value")]
+pub enum NodeKind {
+    Program(Rc<Vec<Node>>),
+    S(Rc<Vec<Node>>),
+    // [n ...]
+    Vector(Rc<Vec<Node>>),
+    // {k v ...}, keys and values in the order they were written.
+    Map(Rc<Vec<(Node, Node)>>),
+    // A special form keyword, see forms.rs.
+    Keyword(#[serde(deserialize_with = "forms::deserialize_keyword")] forms::Keyword),
+    // 'n, `n, ,n, and ,@n.
+    Quote(Rc<Node>),
+    Quasiquote(Rc<Node>),
+    Unquote(Rc<Node>),
+    UnquoteSplicing(Rc<Node>),
+    Ident(Symbol),
+    LitNum(i64),
+    LitFloat(f64),
+    LitBool(bool),
+    LitChar(char),
     LitStr(Str),
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Node {
+    #[serde(flatten)]
+    pub kind: NodeKind,
+    #[serde(default = "lexer::dummy_span")]
+    pub span: Span,
+}
+
+// Nodes are compared structurally, spans are ignored.
+impl PartialEq for Node {
+    fn eq(&self, other: &Node) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl PartialEq<NodeKind> for Node {
+    fn eq(&self, other: &NodeKind) -> bool {
+        self.kind == *other
+    }
+}
+
+impl From<NodeKind> for Node {
+    fn from(kind: NodeKind) -> Node {
+        Node::new(kind, DUMMY_SP)
+    }
+}
+
 impl Node {
+    pub fn new(kind: NodeKind, span: Span) -> Node {
+        Node {
+            kind: kind,
+            span: span,
+        }
+    }
+
+    // Only copies the list if it is shared.
     pub fn push(&mut self, n: Node) {
-        match *self {
-            Node::Program(ref mut ns) | Node::S(ref mut ns) => ns.push(n),
-            _ => panic!("Can't push to {:?}", self),
+        match self.kind {
+            NodeKind::Program(ref mut ns) | NodeKind::S(ref mut ns) => return Rc::make_mut(ns).push(n),
+            _ => {}
         }
+        panic!("Can't push to {:?} at {}", self, self.span);
     }
 
     pub fn is_keyword(&self) -> bool {
-        match *self {
-            Node::Plus | Node::Fn | Node::Let | Node::Print => true,
+        match self.kind {
+            NodeKind::Keyword(_) => true,
             _ => false,
         }
     }
 
     pub fn is_value(&self) -> bool {
-        match *self {
-            Node::LitStr(_) | Node::LitNum(_) => true,
-            Node::S(ref ns) => ns.len() == 0 || &ns[0] == &Node::Fn,
+        match self.kind {
+            NodeKind::LitStr(_) |
+            NodeKind::LitNum(_) |
+            NodeKind::LitFloat(_) |
+            NodeKind::LitBool(_) |
+            NodeKind::LitChar(_) => true,
+            NodeKind::S(ref ns) => ns.len() == 0 || forms::head(self).map_or(false, |f| f.is_value()),
+            // Collections are values once their elements have been evaluated.
+            NodeKind::Vector(ref ns) => ns.iter().all(|n| n.is_value()),
+            NodeKind::Map(ref ps) => ps.iter().all(|&(ref k, ref v)| k.is_value() && v.is_value()),
             _ => false,
         }
 
     }
 
-    pub fn expect_lit_num(&self) -> u32 {
-        if let &Node::LitNum(n) = self {
+    pub fn expect_lit_num(&self) -> i64 {
+        if let NodeKind::LitNum(n) = self.kind {
             n
         } else {
-            panic!("expected LitNum, found {:?}", self)
+            panic!("expected LitNum, found {} at {}", self, self.span)
         }
     }
 
-    pub fn expect_ident(&self) -> &Str {
-        if let &Node::Ident(ref s) = self {
+    pub fn expect_ident(&self) -> Symbol {
+        if let NodeKind::Ident(s) = self.kind {
             s
         } else {
-            panic!("expected Ident, found {:?}", self)
+            panic!("expected Ident, found {} at {}", self, self.span)
         }
     }
 
-    pub fn subst(&self, from: &[Str], to: &[Node]) -> Node {
-        match *self {
-            Node::Program(ref ns) => Node::Program(ns.into_iter().map(|n| n.subst(from, to)).collect()),
-            Node::S(ref ns) => Node::S(ns.into_iter().map(|n| n.subst(from, to)).collect()),
-            Node::Ident(ref s) => {
-                for (f, t) in from.iter().zip(to.iter()) {
-                    if f == s {
-                        return t.clone();
+    // Replaces each identifier in from with the node at the same index in to.
+    // Subtrees with nothing to replace are shared with self, not copied.
+    pub fn subst(&self, from: &[Symbol], to: &[Node]) -> Node {
+        struct Subst<'a> {
+            from: &'a [Symbol],
+            to: &'a [Node],
+        }
+
+        impl<'a> VisitorMut for Subst<'a> {
+            fn visit_node(&mut self, node: &mut Node) {
+                if let NodeKind::Ident(s) = node.kind {
+                    if let Some(i) = self.from.iter().position(|f| *f == s) {
+                        *node = self.to[i].clone();
                     }
+                    return;
                 }
-
-                Node::Ident(s.clone())
+                visit::walk_node_mut(self, node)
             }
-            Node::Plus |
-            Node::Fn |
-            Node::Let |
-            Node::Macro |
-            Node::Print |
-            Node::LitNum(_) |
-            Node::LitStr(_) => self.clone(),
         }
+
+        let mut result = self.clone();
+        Subst {
+            from: from,
+            to: to,
+        }.visit_node(&mut result);
+        result
     }
 }
 
-// Builder macros
+// The value in rc, which is only copied if it is shared.
+pub fn unshare<T: Clone>(rc: Rc<T>) -> T {
+    Rc::try_unwrap(rc).unwrap_or_else(|rc| (*rc).clone())
+}
+
+// Builder macros, these produce nodes with dummy spans. Arguments may be
+// either `Node`s or `NodeKind`s.
 macro_rules! program {
-    ($($ns: expr),*) => (::parser::Node::Program(vec![$($ns),*]))
+    ($($ns: expr),*) => (::parser::Node::from(::parser::NodeKind::Program(::std::rc::Rc::new(vec![$(::parser::Node::from($ns)),*]))))
 }
 macro_rules! s {
-    ($($ns: expr),*) => (::parser::Node::S(vec![$($ns),*]))
+    ($($ns: expr),*) => (::parser::Node::from(::parser::NodeKind::S(::std::rc::Rc::new(vec![$(::parser::Node::from($ns)),*]))))
+}
+macro_rules! vector {
+    ($($ns: expr),*) => (::parser::Node::from(::parser::NodeKind::Vector(::std::rc::Rc::new(vec![$(::parser::Node::from($ns)),*]))))
+}
+macro_rules! map {
+    ($($k: expr => $v: expr),*) => (::parser::Node::from(::parser::NodeKind::Map(::std::rc::Rc::new(vec![$((::parser::Node::from($k), ::parser::Node::from($v))),*]))))
+}
+macro_rules! kw {
+    ($s: expr) => (::parser::Node::from(::parser::NodeKind::Keyword($s)))
+}
+macro_rules! quote {
+    ($n: expr) => (::parser::Node::from(::parser::NodeKind::Quote(::std::rc::Rc::new(::parser::Node::from($n)))))
+}
+macro_rules! quasiquote {
+    ($n: expr) => (::parser::Node::from(::parser::NodeKind::Quasiquote(::std::rc::Rc::new(::parser::Node::from($n)))))
+}
+macro_rules! unquote {
+    ($n: expr) => (::parser::Node::from(::parser::NodeKind::Unquote(::std::rc::Rc::new(::parser::Node::from($n)))))
+}
+macro_rules! unquote_splicing {
+    ($n: expr) => (::parser::Node::from(::parser::NodeKind::UnquoteSplicing(::std::rc::Rc::new(::parser::Node::from($n)))))
 }
 macro_rules! ident {
-    ($s: expr) => (::parser::Node::Ident(::lexer::Str::new($s)))
+    ($s: expr) => (::parser::Node::from(::parser::NodeKind::Ident(::symbol::Symbol::intern($s))))
 }
 macro_rules! lit_num {
-    ($n: expr) => (::parser::Node::LitNum($n))
+    ($n: expr) => (::parser::Node::from(::parser::NodeKind::LitNum($n)))
+}
+macro_rules! lit_float {
+    ($n: expr) => (::parser::Node::from(::parser::NodeKind::LitFloat($n)))
+}
+macro_rules! lit_bool {
+    ($b: expr) => (::parser::Node::from(::parser::NodeKind::LitBool($b)))
+}
+macro_rules! lit_char {
+    ($c: expr) => (::parser::Node::from(::parser::NodeKind::LitChar($c)))
 }
 macro_rules! lit_str {
-    ($s: expr) => (::parser::Node::LitStr(::lexer::Str::new($s)))
+    ($s: expr) => (::parser::Node::from(::parser::NodeKind::LitStr(::lexer::Str::new($s))))
 }
 
-// FIXME could pretty print, if I could be bothered.
+// Prints the node on one line, see pretty.rs for printing across lines.
 impl fmt::Display for Node {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fn write_node_list(ns: &[Node], f: &mut fmt::Formatter) -> fmt::Result {
-            let mut first = true;
-            for n in ns {
-                if first {
-                    first = false;
-                } else {
-                    try!(write!(f, " "));
-                }
-                try!(n.fmt(f));
+        let mut printer = Printer {
+            f: f,
+            result: Ok(()),
+        };
+        printer.visit_node(self);
+        printer.result
+    }
+}
+
+// Writes nodes to f. Once writing fails nothing more is written, result is the
+// error.
+struct Printer<'a, 'b: 'a> {
+    f: &'a mut fmt::Formatter<'b>,
+    result: fmt::Result,
+}
+
+impl<'a, 'b> Printer<'a, 'b> {
+    fn write<T: fmt::Display>(&mut self, x: T) {
+        if self.result.is_ok() {
+            self.result = write!(self.f, "{}", x);
+        }
+    }
+
+    // ns separated by spaces, between open and close.
+    fn list(&mut self, open: &str, ns: &[Node], close: &str) {
+        self.write(open);
+        for (i, n) in ns.iter().enumerate() {
+            if i > 0 {
+                self.write(" ");
             }
+            self.visit_node(n);
+        }
+        self.write(close);
+    }
+}
 
-            Ok(())
+impl<'a, 'b> Visitor for Printer<'a, 'b> {
+    fn visit_program(&mut self, ns: &[Node], _span: Span) {
+        self.list("", ns, "")
+    }
+
+    fn visit_s(&mut self, ns: &[Node], _span: Span) {
+        self.list("(", ns, ")")
+    }
+
+    fn visit_vector(&mut self, ns: &[Node], _span: Span) {
+        self.list("[", ns, "]")
+    }
+
+    fn visit_map(&mut self, ps: &[(Node, Node)], _span: Span) {
+        self.write("{");
+        for (i, &(ref k, ref v)) in ps.iter().enumerate() {
+            if i > 0 {
+                self.write(" ");
+            }
+            self.visit_node(k);
+            self.write(" ");
+            self.visit_node(v);
         }
+        self.write("}");
+    }
+
+    fn visit_quote(&mut self, n: &Node, _span: Span) {
+        self.write("'");
+        self.visit_node(n)
+    }
+
+    fn visit_quasiquote(&mut self, n: &Node, _span: Span) {
+        self.write("`");
+        self.visit_node(n)
+    }
+
+    fn visit_unquote(&mut self, n: &Node, _span: Span) {
+        self.write(",");
+        self.visit_node(n)
+    }
+
+    fn visit_unquote_splicing(&mut self, n: &Node, _span: Span) {
+        self.write(",@");
+        self.visit_node(n)
+    }
 
+    fn visit_keyword(&mut self, k: forms::Keyword, _span: Span) {
+        self.write(k)
+    }
+
+    fn visit_ident(&mut self, s: Symbol, _span: Span) {
+        self.write(s)
+    }
+
+    fn visit_lit(&mut self, node: &Node) {
+        match node.kind {
+            NodeKind::LitNum(n) => self.write(n),
+            NodeKind::LitFloat(n) => self.write(TokenKind::Float(n)),
+            NodeKind::LitBool(b) => self.write(TokenKind::Bool(b)),
+            NodeKind::LitChar(c) => self.write(lexer::char_literal(c)),
+            NodeKind::LitStr(ref s) => self.write(lexer::str_literal(s)),
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Errors.
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ParseErrorKind {
+    // A closing delimiter with no list to close.
+    UnexpectedClose(TokenKind),
+    // A closing delimiter which doesn't match the innermost unclosed list,
+    // includes the closing delimiter, then the opening delimiter and its span.
+    Mismatched(TokenKind, TokenKind, Span),
+    // A list with no closing delimiter, includes the opening delimiter and its
+    // span. The error's span is where the list was assumed to end, either the
+    // last token of the input or the first element which was moved out of
+    // the list (see parse_recovering).
+    Unclosed(TokenKind, Span),
+    // A prefix (e.g., `'`) which is not followed by a datum, includes the
+    // prefix.
+    MissingDatum(TokenKind),
+    // A map literal with an odd number of elements.
+    KeyWithoutValue,
+    // An error from the lexer.
+    Lex(LexErrorKind),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: Span) -> ParseError {
+        ParseError {
+            kind: kind,
+            span: span,
+        }
+    }
+}
+
+impl From<LexError> for ParseError {
+    fn from(e: LexError) -> ParseError {
+        ParseError::new(ParseErrorKind::Lex(e.kind), e.span)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Node::Program(ref ns) => {
-                try!(write_node_list(ns, f));
+            ParseErrorKind::UnexpectedClose(ref t) => write!(f, "unexpected `{}`", t),
+            ParseErrorKind::Mismatched(ref t, ref open, open_span) => {
+                write!(f,
+                       "unexpected `{}`, expected `{}` to close `{}` at {}",
+                       t,
+                       open.closing().unwrap(),
+                       open,
+                       open_span)
             }
-            Node::S(ref ns) => {
-                try!(write!(f, "("));
-                try!(write_node_list(ns, f));
-                try!(write!(f, ")"));
+            ParseErrorKind::Unclosed(ref open, open_span) => {
+                write!(f, "expected `{}` to close `{}` at {}", open.closing().unwrap(), open, open_span)
             }
-            Node::Plus => try!(write!(f, "+")),
-            Node::Fn => try!(write!(f, "fn")),
-            Node::Let => try!(write!(f, "let")),
-            Node::Macro => try!(write!(f, "macro")),
-            Node::Print => try!(write!(f, "print")),
-            Node::Ident(ref s) => try!(write!(f, "{}", s)),
-            Node::LitNum(n) => try!(write!(f, "{}", n)),
-            Node::LitStr(ref s) => try!(write!(f, "{}", s)),
+            ParseErrorKind::MissingDatum(ref p) => write!(f, "expected an expression after `{}`", p),
+            ParseErrorKind::KeyWithoutValue => write!(f, "key without a value in map literal"),
+            ParseErrorKind::Lex(ref k) => k.fmt(f),
         }
+    }
+}
 
-        Ok(())
+
+// Parsing.
+
+// The node for a prefix token (e.g., `'`) applied to n.
+pub fn prefix_kind(tok: &TokenKind, n: Node) -> NodeKind {
+    match *tok {
+        TokenKind::Quote => NodeKind::Quote(Rc::new(n)),
+        TokenKind::Quasiquote => NodeKind::Quasiquote(Rc::new(n)),
+        TokenKind::Unquote => NodeKind::Unquote(Rc::new(n)),
+        TokenKind::UnquoteSplicing => NodeKind::UnquoteSplicing(Rc::new(n)),
+        _ => unreachable!(),
     }
 }
 
-pub fn parse(input: &[Token]) -> Node {
-    let mut expr_stack = Vec::new();
-    let mut cur_node = Node::Program(Vec::new());
-    let mut i = 0;
-    loop {
-        if i >= input.len() {
-            break;
+// The node for a bracketed list, open is the opening delimiter and ns the
+// elements. span is the span of the whole list.
+pub fn list_kind(open: &TokenKind, ns: Vec<Node>, span: Span) -> Result<NodeKind, ParseError> {
+    Ok(match *open {
+        TokenKind::Bra => NodeKind::S(Rc::new(ns)),
+        TokenKind::SquareBra => NodeKind::Vector(Rc::new(ns)),
+        TokenKind::CurlyBra => {
+            if ns.len() % 2 != 0 {
+                return Err(ParseError::new(ParseErrorKind::KeyWithoutValue, span));
+            }
+            let mut ps = Vec::with_capacity(ns.len() / 2);
+            let mut ns = ns.into_iter();
+            while let (Some(k), Some(v)) = (ns.next(), ns.next()) {
+                ps.push((k, v));
+            }
+            NodeKind::Map(Rc::new(ps))
+        }
+        _ => unreachable!(),
+    })
+}
+
+// The node for a token which is not a paren, prefix, comment, or whitespace.
+pub fn leaf_kind(tok: &TokenKind) -> NodeKind {
+    match *tok {
+        TokenKind::Keyword(k) => NodeKind::Keyword(k),
+        TokenKind::Name(s) => NodeKind::Ident(s),
+        TokenKind::Number(n) => NodeKind::LitNum(n),
+        TokenKind::Float(n) => NodeKind::LitFloat(n),
+        TokenKind::Bool(b) => NodeKind::LitBool(b),
+        TokenKind::Char(c) => NodeKind::LitChar(c),
+        TokenKind::Str(ref s) => NodeKind::LitStr(s.clone()),
+        _ => unreachable!(),
+    }
+}
+
+// Parses the whole input as a Program, or returns the first syntax error.
+pub fn parse(input: &[Token]) -> Result<Node, ParseError> {
+    let (program, mut errors) = parse_recovering(input);
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(errors.remove(0))
+    }
+}
+
+// Lexes and parses input as a Program, or returns the first lexing or syntax
+// error.
+pub fn parse_str(input: &str) -> Result<Node, ParseError> {
+    let toks = try!(lexer::lex(input).map_err(|mut errs| ParseError::from(errs.remove(0))));
+    parse(&toks)
+}
+
+// Parses the whole input as a Program, recovering from syntax errors. Returns
+// every error, in the order they occur in the input, along with as much of the
+// program as could be parsed.
+//
+// Unclosed lists are closed before the first element which starts on a later
+// line and at or left of the list's opening delimiter, since that is where
+// indentation suggests the list was meant to end. A closing delimiter which
+// doesn't match the innermost list closes the enclosing list it does match (if
+// there is one) or else is treated as if it were the right delimiter. Other
+// stray delimiters and prefixes are skipped, as is the key of a map literal
+// without a value.
+pub fn parse_recovering(input: &[Token]) -> (Node, Vec<ParseError>) {
+    // The tokens are pushed to the parser, it never pulls from its iterator.
+    let mut parser = Parser::new(iter::empty::<Result<Token, LexError>>());
+    for tok in input {
+        parser.token(tok.clone());
+    }
+    parser.eof();
+
+    let mut span = Span::new(0, 0, 1, 1);
+    if let Some(last) = input.last() {
+        span.hi = last.span.hi;
+    }
+    let mut errors = parser.errors;
+    errors.sort_by_key(|e| e.span.lo);
+    (Node::new(NodeKind::Program(Rc::new(parser.ready.into_iter().collect())), span), errors)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ParseStatus {
+    // The input is zero or more complete forms.
+    Complete(Vec<Node>),
+    // The input stops part way through a form. open is the number of unclosed
+    // lists (of any kind), in_string and in_comment are true if the input
+    // ends inside a string literal or a block or datum comment.
+    Incomplete {
+        open: usize,
+        in_string: bool,
+        in_comment: bool,
+    },
+    // The input has errors which more input can't fix.
+    Error(Vec<ParseError>),
+}
+
+// For input which arrives a piece at a time, e.g., typed at a REPL: tells
+// whether the input so far is complete, needs more, or is wrong. An
+// unexpected end of input is the only error which leaves input Incomplete.
+pub fn parse_incremental(input: &str) -> ParseStatus {
+    let mut parser = Parser::new(iter::empty::<Result<Token, LexError>>());
+    let mut in_string = false;
+    let mut in_comment = false;
+    for tok in lexer::Lexer::new(input) {
+        match tok {
+            Ok(tok) => parser.token(tok),
+            // These errors run to the end of the input, more input might
+            // fix them.
+            Err(LexError { kind: LexErrorKind::UnterminatedString, .. }) => in_string = true,
+            Err(LexError { kind: LexErrorKind::UnterminatedComment, .. }) => in_comment = true,
+            Err(LexError { kind: LexErrorKind::MissingDatum, span }) if span.hi == input.len() => in_comment = true,
+            Err(e) => parser.errors.push(ParseError::from(e)),
         }
+    }
+
+    if !parser.errors.is_empty() {
+        return ParseStatus::Error(parser.errors);
+    }
+    if in_string || in_comment || !parser.at_top_level() {
+        return ParseStatus::Incomplete {
+            open: parser.expr_stack.len(),
+            in_string: in_string,
+            in_comment: in_comment,
+        };
+    }
+    ParseStatus::Complete(parser.ready.into_iter().collect())
+}
+
+// An iterator of top-level forms, parsed from a stream of tokens (and lexing
+// errors) such as a lexer::Lexer. Each form is returned as soon as its last
+// token has been read, without reading any further.
+//
+// Errors are returned instead of the form they occur in, when it is complete,
+// or at the end of the input. A syntax error outside of any form is returned
+// as soon as it is found. Syntax errors are recovered from in the same way as
+// parse_recovering.
+pub struct Parser<I> {
+    tokens: I,
+    // Unclosed lists and their opening delimiters, innermost last. The
+    // elements of every kind of list are collected in an S node.
+    expr_stack: Vec<(TokenKind, Node)>,
+    // Prefixes waiting for the datum they apply to, with the depth of
+    // expr_stack where they were found.
+    prefixes: Vec<(TokenKind, Span, usize)>,
+    // Complete top-level forms which haven't been returned yet.
+    ready: VecDeque<Node>,
+    errors: Vec<ParseError>,
+    // The span of the last token read.
+    last: Span,
+}
+
+impl<I: Iterator<Item = Result<Token, LexError>>> Parser<I> {
+    pub fn new(tokens: I) -> Parser<I> {
+        Parser {
+            tokens: tokens,
+            expr_stack: Vec::new(),
+            prefixes: Vec::new(),
+            ready: VecDeque::new(),
+            errors: Vec::new(),
+            last: DUMMY_SP,
+        }
+    }
+
+    fn error(&mut self, kind: ParseErrorKind, span: Span) {
+        self.errors.push(ParseError::new(kind, span));
+    }
 
-        match input[i] {
-            Token::Bra => {
-                expr_stack.push(cur_node);
-                cur_node = Node::S(Vec::new());
+    fn token(&mut self, tok: Token) {
+        let span = tok.span;
+        self.last = span;
+        let kind = match tok.kind {
+            TokenKind::Bra | TokenKind::SquareBra | TokenKind::CurlyBra => {
+                self.expr_stack.push((tok.kind, Node::new(NodeKind::S(Rc::new(Vec::new())), span)));
+                return;
             }
-            Token::Ket => {
-                match cur_node {
-                    Node::S(..) => {
-                        let old_cur = cur_node;
-                        cur_node = expr_stack.pop().unwrap();
-                        cur_node.push(old_cur);
+            TokenKind::Ket | TokenKind::SquareKet | TokenKind::CurlyKet => {
+                self.missing_datum();
+                let mismatched = match self.expr_stack.last() {
+                    None => {
+                        self.error(ParseErrorKind::UnexpectedClose(tok.kind), span);
+                        return;
+                    }
+                    Some(&(ref open, ref node)) if open.closing().as_ref() != Some(&tok.kind) => {
+                        Some(ParseErrorKind::Mismatched(tok.kind.clone(), open.clone(), node.span))
+                    }
+                    Some(_) => None,
+                };
+                if let Some(e) = mismatched {
+                    self.error(e, span);
+                    let matching = self.expr_stack
+                        .iter()
+                        .rposition(|&(ref open, _)| open.closing().as_ref() == Some(&tok.kind));
+                    if let Some(i) = matching {
+                        while self.expr_stack.len() > i + 1 {
+                            self.close_implicitly();
+                        }
                     }
-                    _ => panic!("Unexpected `)`"),
                 }
+                let (open, node) = self.expr_stack.pop().unwrap();
+                let ns = match node.kind {
+                    NodeKind::S(ns) => unshare(ns),
+                    _ => unreachable!(),
+                };
+                self.complete_list(&open, ns, node.span.to(span));
+                return;
+            }
+            TokenKind::Quote | TokenKind::Quasiquote | TokenKind::Unquote | TokenKind::UnquoteSplicing => {
+                self.prefixes.push((tok.kind, span, self.expr_stack.len()));
+                return;
             }
+            TokenKind::Comment(_) | TokenKind::Whitespace(_) => return,
+            ref t => leaf_kind(t),
+        };
+        self.complete(Node::new(kind, span));
+    }
+
+    // Closes any lists left open at the end of the input.
+    fn eof(&mut self) {
+        while !self.expr_stack.is_empty() {
+            let (open, open_span) = {
+                let &(ref open, ref node) = self.expr_stack.last().unwrap();
+                (open.clone(), node.span)
+            };
+            let span = self.close_implicitly().unwrap_or(self.last);
+            self.error(ParseErrorKind::Unclosed(open, open_span), span);
+        }
+        self.missing_datum();
+    }
 
-            Token::Keyword("+") => cur_node.push(Node::Plus),
-            Token::Keyword("fn") => cur_node.push(Node::Fn),
-            Token::Keyword("let") => cur_node.push(Node::Let),
-            Token::Keyword("macro") => cur_node.push(Node::Macro),
-            Token::Keyword("print") => cur_node.push(Node::Print),
-            Token::Name(ref s) => cur_node.push(Node::Ident(s.clone())),
-            Token::Number(n) => cur_node.push(Node::LitNum(n)),
-            Token::Str(ref s) => cur_node.push(Node::LitStr(s.clone())),
+    // Discards the prefixes waiting at the current depth, which have no datum
+    // to apply to.
+    fn missing_datum(&mut self) {
+        let depth = self.expr_stack.len();
+        if let Some(&(ref p, p_span, _)) = self.prefixes.last().filter(|p| p.2 == depth) {
+            self.errors.push(ParseError::new(ParseErrorKind::MissingDatum(p.clone()), p_span));
+        }
+        while self.prefixes.last().map_or(false, |p| p.2 == depth) {
+            self.prefixes.pop();
+        }
+    }
+
+    // Closes the innermost list, which has no closing delimiter. Elements which
+    // start on a later line than the list, at or left of its opening
+    // delimiter, are moved to the enclosing list; returns the span of the first
+    // one moved.
+    fn close_implicitly(&mut self) -> Option<Span> {
+        self.missing_datum();
+        let (open, node) = self.expr_stack.pop().unwrap();
+        let open_span = node.span;
+        let mut ns = match node.kind {
+            NodeKind::S(ns) => unshare(ns),
             _ => unreachable!(),
+        };
+        let split = ns.iter()
+            .position(|n| n.span.line > open_span.line && n.span.col <= open_span.col)
+            .unwrap_or(ns.len());
+        let rest = ns.split_off(split);
+        let span = ns.last().map_or(open_span, |n| open_span.to(n.span));
+        self.complete_list(&open, ns, span);
+
+        let moved = rest.first().map(|n| n.span);
+        for n in rest {
+            self.complete(n);
         }
+        moved
+    }
 
-        i += 1;
+    fn complete_list(&mut self, open: &TokenKind, ns: Vec<Node>, span: Span) {
+        let mut ns = ns;
+        if *open == TokenKind::CurlyBra && ns.len() % 2 != 0 {
+            self.error(ParseErrorKind::KeyWithoutValue, span);
+            ns.pop();
+        }
+        // Can't fail now the map (if it is one) has a value for every key.
+        let kind = list_kind(open, ns, span).unwrap();
+        self.complete(Node::new(kind, span));
     }
 
-    debug!("parsed: {:?}", expr_stack);
-    assert!(expr_stack.is_empty(), "Unexpected EOF");
+    // node is a complete datum; wraps it in any prefixes and adds it to the
+    // enclosing s-expression, or to the ready forms if it is top-level.
+    fn complete(&mut self, node: Node) {
+        let node = apply_prefixes(node, &mut self.prefixes, self.expr_stack.len());
+        match self.expr_stack.last_mut() {
+            Some(&mut (_, ref mut parent)) => parent.push(node),
+            None => self.ready.push_back(node),
+        }
+    }
+
+    // Discards any partially parsed form and returns the pending errors.
+    fn take_errors(&mut self) -> Vec<ParseError> {
+        self.expr_stack.clear();
+        self.prefixes.clear();
+        mem::replace(&mut self.errors, Vec::new())
+    }
+
+    fn at_top_level(&self) -> bool {
+        self.expr_stack.is_empty() && self.prefixes.is_empty()
+    }
+}
+
+impl<I: Iterator<Item = Result<Token, LexError>>> Iterator for Parser<I> {
+    type Item = Result<Node, Vec<ParseError>>;
+
+    fn next(&mut self) -> Option<Result<Node, Vec<ParseError>>> {
+        loop {
+            if let Some(node) = self.ready.pop_front() {
+                debug!("parsed: {:?}", node);
+                if !self.errors.is_empty() {
+                    return Some(Err(mem::replace(&mut self.errors, Vec::new())));
+                }
+                return Some(Ok(node));
+            }
+
+            match self.tokens.next() {
+                Some(Ok(tok)) => {
+                    let errors = self.errors.len();
+                    self.token(tok);
+                    if self.errors.len() > errors && self.ready.is_empty() && self.at_top_level() {
+                        return Some(Err(self.take_errors()));
+                    }
+                }
+                Some(Err(e)) => self.errors.push(ParseError::from(e)),
+                None => {
+                    // A lexing error probably explains any unclosed lists,
+                    // so don't complain about them.
+                    if self.errors.iter().any(|e| if let ParseErrorKind::Lex(_) = e.kind { true } else { false }) {
+                        return Some(Err(self.take_errors()));
+                    }
+                    self.eof();
+                    if self.ready.is_empty() {
+                        if self.errors.is_empty() {
+                            return None;
+                        }
+                        return Some(Err(self.take_errors()));
+                    }
+                }
+            }
+        }
+    }
+}
 
-    match cur_node {
-        Node::Program(..) => cur_node,
-        _ => panic!("Expected Program, found {:?}", cur_node),
+// Wraps n in the prefixes waiting at depth, innermost (i.e., last) first.
+fn apply_prefixes(n: Node, prefixes: &mut Vec<(TokenKind, Span, usize)>, depth: usize) -> Node {
+    let mut n = n;
+    while prefixes.last().map_or(false, |p| p.2 == depth) {
+        let (tok, span, _) = prefixes.pop().unwrap();
+        let span = span.to(n.span);
+        n = Node::new(prefix_kind(&tok, n), span);
     }
+    n
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use lexer::lex;
+    use lexer::{self, Token, TokenKind, Span};
+    use std::cell::Cell;
+
+    fn lex(input: &str) -> Vec<Token> {
+        lexer::lex(input).unwrap()
+    }
 
     #[test]
     fn test_empty() {
-        assert!(parse(&lex("")) == Node::Program(Vec::new()));
+        assert!(parse(&lex("")).unwrap() == NodeKind::Program(Rc::new(Vec::new())));
     }
 
     #[test]
     fn test_simple() {
-        assert!(parse(&lex("fn")) == program!(Node::Fn));
-        assert!(parse(&lex("let")) == program!(Node::Let));
-        assert!(parse(&lex("+")) == program!(Node::Plus));
-        assert!(parse(&lex("42")) == program!(lit_num!(42)));
-        assert!(parse(&lex("foo")) == program!(ident!("foo")));
-        assert!(parse(&lex("a b")) == program!(ident!("a"), ident!("b")));
-        assert!(parse(&lex("(+ 1 2)")) == program!(s!(Node::Plus, lit_num!(1), lit_num!(2))));
+        assert!(parse(&lex("fn")).unwrap() == program!(kw!("fn")));
+        assert!(parse(&lex("let")).unwrap() == program!(kw!("let")));
+        assert!(parse(&lex("+")).unwrap() == program!(kw!("+")));
+        assert!(parse(&lex("42")).unwrap() == program!(lit_num!(42)));
+        assert!(parse(&lex("-0x2a 4.2e1")).unwrap() == program!(lit_num!(-42), lit_float!(42.0)));
+        assert!(parse(&lex("foo")).unwrap() == program!(ident!("foo")));
+        assert!(parse(&lex("a b")).unwrap() == program!(ident!("a"), ident!("b")));
+        assert!(parse(&lex("(+ 1 2)")).unwrap() == program!(s!(kw!("+"), lit_num!(1), lit_num!(2))));
     }
 
 
     #[test]
     fn test_realistic() {
-        assert!(parse(&lex("(print \"Hello world!\")")) ==
-                program!(s!(Node::Print, lit_str!("Hello world!"))));
-        assert!(parse(&lex("a (let a 42 (fn x (+ x a)))")) ==
+        assert!(parse(&lex("(print \"Hello world!\")")).unwrap() ==
+                program!(s!(kw!("print"), lit_str!("Hello world!"))));
+        assert!(parse(&lex("a (let a 42 (fn x (+ x a)))")).unwrap() ==
                 program!(ident!("a"),
-                         s!(Node::Let,
+                         s!(kw!("let"),
                             ident!("a"),
                             lit_num!(42),
-                            s!(Node::Fn,
+                            s!(kw!("fn"),
                                ident!("x"),
-                               s!(Node::Plus, ident!("x"), ident!("a"))))));
-        assert!(parse(&lex("((fn x (+ x 42))(+ 3 \"a string\"))")) ==
-                program!(s!(s!(Node::Fn, ident!("x"), s!(Node::Plus, ident!("x"), lit_num!(42))),
-                            s!(Node::Plus, lit_num!(3), lit_str!("a string")))));
+                               s!(kw!("+"), ident!("x"), ident!("a"))))));
+        assert!(parse(&lex("((fn x (+ x 42))(+ 3 \"a string\"))")).unwrap() ==
+                program!(s!(s!(kw!("fn"), ident!("x"), s!(kw!("+"), ident!("x"), lit_num!(42))),
+                            s!(kw!("+"), lit_num!(3), lit_str!("a string")))));
+    }
+
+    fn parse_err(input: &str) -> ParseError {
+        parse(&lex(input)).unwrap_err()
     }
 
     #[test]
-    #[should_panic]
     fn test_fail_unclosed() {
-        parse(&lex("("));
+        assert!(parse_err("(") ==
+                ParseError::new(ParseErrorKind::Unclosed(TokenKind::Bra, Span::new(0, 1, 1, 1)), Span::new(0, 1, 1, 1)));
     }
 
     #[test]
-    #[should_panic]
     fn test_fail_unclosed2() {
-        parse(&lex("((fn x a) baz 42 "));
+        let e = parse_err("((fn x a) baz 42 ");
+        assert!(e == ParseError::new(ParseErrorKind::Unclosed(TokenKind::Bra, Span::new(0, 1, 1, 1)),
+                                     Span::new(14, 16, 1, 15)));
+        assert!(e.to_string() == "1:15: expected `)` to close `(` at 1:1");
     }
 
     #[test]
-    #[should_panic]
     fn test_fail_too_closed() {
-        parse(&lex("(baz 42 ))"));
+        let e = parse_err("(baz 42 ))");
+        assert!(e == ParseError::new(ParseErrorKind::UnexpectedClose(TokenKind::Ket), Span::new(9, 10, 1, 10)));
+        assert!(e.to_string() == "1:10: unexpected `)`");
     }
 
     #[test]
-    #[should_panic]
     fn test_fail_too_closed2() {
-        parse(&lex(")"));
+        assert!(parse_err(")") ==
+                ParseError::new(ParseErrorKind::UnexpectedClose(TokenKind::Ket), Span::new(0, 1, 1, 1)));
+    }
+
+    #[test]
+    fn test_spans() {
+        let ast = parse(&lex("a\n(let x\n  (+ x 1))")).unwrap();
+        assert!(ast.span == Span::new(0, 19, 1, 1));
+        if let NodeKind::Program(ref ns) = ast.kind {
+            assert!(ns[0].span == Span::new(0, 1, 1, 1));
+            assert!(ns[1].span == Span::new(2, 19, 2, 1));
+            if let NodeKind::S(ref ns) = ns[1].kind {
+                assert!(ns[0].span == Span::new(3, 6, 2, 2));
+                assert!(ns[2].span == Span::new(11, 18, 3, 3));
+            } else {
+                panic!();
+            }
+        } else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let input = r#"(print "a \"quoted\" string\n" r"raw\n" 42 (fn x (+ x 1)))"#;
+        let ast = parse(&lex(input)).unwrap();
+        assert!(ast.to_string() == r#"(print "a \"quoted\" string\n" "raw\\n" 42 (fn x (+ x 1)))"#);
+        assert!(parse(&lex(&ast.to_string())).unwrap() == ast);
+        let ast = parse(&lex("(+ -1 1.0 2e-3 1_000)")).unwrap();
+        assert!(ast.to_string() == "(+ -1 1.0 0.002 1000)");
+    }
+
+    #[test]
+    fn test_comments() {
+        let toks = lexer::lex_with_comments("; hello\n(print #;foo \"Hello world!\") #| bye |#").unwrap();
+        assert!(parse(&toks).unwrap() == program!(s!(kw!("print"), lit_str!("Hello world!"))));
+    }
+
+    #[test]
+    fn test_quotes() {
+        assert!(parse(&lex("'x")).unwrap() == program!(quote!(ident!("x"))));
+        assert!(parse(&lex("'(a 'b) c")).unwrap() == program!(quote!(s!(ident!("a"), quote!(ident!("b")))), ident!("c")));
+        assert!(parse(&lex("`(a ,b ,@(c d))")).unwrap() ==
+                program!(quasiquote!(s!(ident!("a"), unquote!(ident!("b")), unquote_splicing!(s!(ident!("c"), ident!("d")))))));
+        assert!(parse(&lex("''x")).unwrap() == program!(quote!(quote!(ident!("x")))));
+        assert!(parse(&lex("(a ' b)")).unwrap() == program!(s!(ident!("a"), quote!(ident!("b")))));
+
+        let ast = parse(&lex("(a '(b c))")).unwrap();
+        if let NodeKind::Program(ref ns) = ast.kind {
+            if let NodeKind::S(ref ns) = ns[0].kind {
+                assert!(ns[1].span == Span::new(3, 9, 1, 4));
+            } else {
+                panic!();
+            }
+        } else {
+            panic!();
+        }
+        assert!(ast.to_string() == "(a '(b c))");
+        let ast = parse(&lex("`(a ,b ,@c)")).unwrap();
+        assert!(ast.to_string() == "`(a ,b ,@c)");
+    }
+
+    #[test]
+    fn test_fail_quote_eof() {
+        let e = parse_err("(a) '");
+        assert!(e == ParseError::new(ParseErrorKind::MissingDatum(TokenKind::Quote), Span::new(4, 5, 1, 5)));
+        assert!(e.to_string() == "1:5: expected an expression after `'`");
+    }
+
+    #[test]
+    fn test_fail_quote_ket() {
+        assert!(parse_err("(a ')") ==
+                ParseError::new(ParseErrorKind::MissingDatum(TokenKind::Quote), Span::new(3, 4, 1, 4)));
+    }
+
+    #[test]
+    fn test_stream() {
+        let toks = lex("(+ 1 2) '(a\n b) c");
+        let read = Cell::new(0);
+        let mut parser = Parser::new(toks.iter().cloned().inspect(|_| read.set(read.get() + 1)).map(Ok));
+        // Each form is returned as soon as it is complete.
+        assert!(parser.next().unwrap().unwrap() == s!(kw!("+"), lit_num!(1), lit_num!(2)));
+        assert!(read.get() == 5);
+        let n = parser.next().unwrap().unwrap();
+        assert!(n == quote!(s!(ident!("a"), ident!("b"))));
+        assert!(n.span == Span::new(8, 15, 1, 9));
+        assert!(read.get() == 10);
+        assert!(parser.next().unwrap().unwrap() == ident!("c"));
+        assert!(parser.next().is_none());
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let mut parser = Parser::new(lexer::Lexer::new("1 (a \"\\q\" b) 2 (\"x"));
+        assert!(parser.next().unwrap().unwrap() == lit_num!(1));
+        let errs = parser.next().unwrap().unwrap_err();
+        assert!(errs.len() == 1 && errs[0].kind == ParseErrorKind::Lex(lexer::LexErrorKind::UnknownEscape('q')));
+        assert!(parser.next().unwrap().unwrap() == lit_num!(2));
+        let errs = parser.next().unwrap().unwrap_err();
+        assert!(errs.len() == 1 && errs[0].kind == ParseErrorKind::Lex(lexer::LexErrorKind::UnterminatedString));
+        assert!(parser.next().is_none());
+
+        // Parsing continues after a syntax error.
+        let mut parser = Parser::new(lexer::Lexer::new("1 ) (a ']) 2"));
+        assert!(parser.next().unwrap().unwrap() == lit_num!(1));
+        let errs = parser.next().unwrap().unwrap_err();
+        assert!(errs.len() == 1 && errs[0].kind == ParseErrorKind::UnexpectedClose(TokenKind::Ket));
+        let errs = parser.next().unwrap().unwrap_err();
+        assert!(errs.len() == 2 && errs[0].kind == ParseErrorKind::MissingDatum(TokenKind::Quote));
+        let errs = parser.next().unwrap().unwrap_err();
+        assert!(errs.len() == 1 && errs[0].kind == ParseErrorKind::UnexpectedClose(TokenKind::Ket));
+        assert!(parser.next().unwrap().unwrap() == lit_num!(2));
+        assert!(parser.next().is_none());
+    }
+
+    #[test]
+    fn test_collections() {
+        assert!(parse(&lex("[]")).unwrap() == program!(vector!()));
+        assert!(parse(&lex("{}")).unwrap() == program!(map!()));
+        assert!(parse(&lex("[1 (a b) [c]]")).unwrap() ==
+                program!(vector!(lit_num!(1), s!(ident!("a"), ident!("b")), vector!(ident!("c")))));
+        assert!(parse(&lex("{\"a\" 1 b [2]}")).unwrap() ==
+                program!(map!(lit_str!("a") => lit_num!(1), ident!("b") => vector!(lit_num!(2)))));
+        assert!(parse(&lex("'[a ,b]")).unwrap() == program!(quote!(vector!(ident!("a"), unquote!(ident!("b"))))));
+
+        let ast = parse(&lex("(f [1 2] {a 'b})")).unwrap();
+        assert!(ast.to_string() == "(f [1 2] {a 'b})");
+        if let NodeKind::Program(ref ns) = ast.kind {
+            if let NodeKind::S(ref ns) = ns[0].kind {
+                assert!(ns[1].span == Span::new(3, 8, 1, 4));
+                assert!(ns[2].span == Span::new(9, 15, 1, 10));
+            } else {
+                panic!();
+            }
+        } else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn test_fail_mismatched() {
+        let e = parse_err("(a [b)]");
+        assert!(e == ParseError::new(ParseErrorKind::Mismatched(TokenKind::Ket, TokenKind::SquareBra, Span::new(3, 4, 1, 4)),
+                                     Span::new(5, 6, 1, 6)));
+        assert!(e.to_string() == "1:6: unexpected `)`, expected `]` to close `[` at 1:4");
+    }
+
+    #[test]
+    fn test_fail_map_odd() {
+        let e = parse_err("(f {a 1 b})");
+        assert!(e == ParseError::new(ParseErrorKind::KeyWithoutValue, Span::new(3, 10, 1, 4)));
+        assert!(e.to_string() == "1:4: key without a value in map literal");
+    }
+
+    #[test]
+    fn test_recover_indentation() {
+        let input = "(define (f x)\n  (+ x 1)\n\n(define (g y)\n  (* y\n 2))";
+        let (ast, errs) = parse_recovering(&lex(input));
+        assert!(ast == program!(s!(ident!("define"), s!(ident!("f"), ident!("x")), s!(kw!("+"), ident!("x"), lit_num!(1))),
+                                s!(ident!("define"), s!(ident!("g"), ident!("y")), s!(ident!("*"), ident!("y"), lit_num!(2)))));
+        // Only unclosed lists are changed, `2` stays in `(*`.
+        assert!(errs == vec![ParseError::new(ParseErrorKind::Unclosed(TokenKind::Bra, Span::new(0, 1, 1, 1)),
+                                             Span::new(25, 50, 4, 1))]);
+        assert!(errs[0].to_string() == "4:1: expected `)` to close `(` at 1:1");
+        if let NodeKind::Program(ref ns) = ast.kind {
+            assert!(ns[0].span == Span::new(0, 23, 1, 1));
+        } else {
+            panic!();
+        }
+
+        // Well indented but unusual code is fine.
+        assert!(parse(&lex("  (a
+b)")).unwrap() == program!(s!(ident!("a"), ident!("b"))));
+    }
+
+    #[test]
+    fn test_recover_mismatched() {
+        // A missing `]`.
+        let (ast, errs) = parse_recovering(&lex("(a [b\n c)"));
+        assert!(ast == program!(s!(ident!("a"), vector!(ident!("b")), ident!("c"))));
+        assert!(errs.len() == 1);
+        assert!(errs[0].kind == ParseErrorKind::Mismatched(TokenKind::Ket, TokenKind::SquareBra, Span::new(3, 4, 1, 4)));
+        // A typo.
+        let (ast, errs) = parse_recovering(&lex("(a (b] c)"));
+        assert!(ast == program!(s!(ident!("a"), s!(ident!("b")), ident!("c"))));
+        assert!(errs.len() == 1);
+    }
+
+    #[test]
+    fn test_recover_all() {
+        let (ast, errs) = parse_recovering(&lex(") (a '] {b 1 c}\n(d e"));
+        assert!(ast == program!(s!(ident!("a")), map!(ident!("b") => lit_num!(1)), s!(ident!("d"), ident!("e"))));
+        let kinds: Vec<_> = errs.into_iter().map(|e| e.kind).collect();
+        assert!(kinds ==
+                vec![ParseErrorKind::UnexpectedClose(TokenKind::Ket),
+                     ParseErrorKind::MissingDatum(TokenKind::Quote),
+                     ParseErrorKind::Mismatched(TokenKind::SquareKet, TokenKind::Bra, Span::new(2, 3, 1, 3)),
+                     ParseErrorKind::KeyWithoutValue,
+                     ParseErrorKind::Unclosed(TokenKind::Bra, Span::new(16, 17, 2, 1))]);
+        // The first error is the one parse returns.
+        assert!(parse(&lex(") (a")).unwrap_err().kind == ParseErrorKind::UnexpectedClose(TokenKind::Ket));
+    }
+
+    #[test]
+    fn test_incremental() {
+        fn incomplete(open: usize, in_string: bool, in_comment: bool) -> ParseStatus {
+            ParseStatus::Incomplete {
+                open: open,
+                in_string: in_string,
+                in_comment: in_comment,
+            }
+        }
+
+        assert!(parse_incremental("") == ParseStatus::Complete(vec![]));
+        assert!(parse_incremental("1 (a b) ; done") ==
+                ParseStatus::Complete(vec![lit_num!(1), s!(ident!("a"), ident!("b"))]));
+        assert!(parse_incremental("(let x [1\n") == incomplete(2, false, false));
+        assert!(parse_incremental("(a) '") == incomplete(0, false, false));
+        assert!(parse_incremental("(print \"abc\n") == incomplete(1, true, false));
+        assert!(parse_incremental("(a #| b") == incomplete(1, false, true));
+        assert!(parse_incremental("(a #;") == incomplete(1, false, true));
+        assert!(parse_incremental("(a #; (b") == incomplete(1, false, true));
+
+        // More input can't fix these.
+        match parse_incremental("(a b))") {
+            ParseStatus::Error(errs) => assert!(errs == vec![ParseError::new(ParseErrorKind::UnexpectedClose(TokenKind::Ket), Span::new(5, 6, 1, 6))]),
+            s => panic!("{:?}", s),
+        }
+        match parse_incremental("(a \"\\q\" (b") {
+            ParseStatus::Error(errs) => assert!(errs[0].kind == ParseErrorKind::Lex(lexer::LexErrorKind::UnknownEscape('q'))),
+            s => panic!("{:?}", s),
+        }
+        match parse_incremental("(a #;)") {
+            ParseStatus::Error(errs) => assert!(errs[0].kind == ParseErrorKind::Lex(lexer::LexErrorKind::MissingDatum)),
+            s => panic!("{:?}", s),
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        let ast = parse(&lex("(f '[1 2.5] {\"k\" #\\a} #t)")).unwrap();
+        let json = ::serde_json::to_string(&ast).unwrap();
+        let back: Node = ::serde_json::from_str(&json).unwrap();
+        assert!(back == ast);
+        if let (&NodeKind::Program(ref a), &NodeKind::Program(ref b)) = (&ast.kind, &back.kind) {
+            assert!(a[0].span == b[0].span);
+        } else {
+            panic!();
+        }
+
+        // Spans are optional.
+        let json = r#"{"kind": "Program", "value": [
+                         {"kind": "S", "value": [{"kind": "Keyword", "value": "+"}, {"kind": "LitNum", "value": 1}]},
+                         {"kind": "Map", "value": [[{"kind": "LitStr", "value": "a"}, {"kind": "Ident", "value": "b"}]]},
+                         {"kind": "Quote", "value": {"kind": "LitChar", "value": "c"}}]}"#;
+        let ast: Node = ::serde_json::from_str(json).unwrap();
+        assert!(ast == program!(s!(kw!("+"), lit_num!(1)), map!(lit_str!("a") => ident!("b")), quote!(lit_char!('c'))));
+        assert!(::serde_json::to_string(&lit_bool!(true)).unwrap() ==
+                r#"{"kind":"LitBool","value":true,"span":{"lo":0,"hi":0,"line":0,"col":0}}"#);
+        assert!(::serde_json::from_str::<Node>(r#"{"kind": "Keyword", "value": "nope"}"#).is_err());
+        assert!(::serde_json::from_str::<Node>(r#"{"kind": "Nope"}"#).is_err());
+    }
+
+    #[test]
+    fn test_bools_chars() {
+        let ast = parse(&lex("(f #t #false #\\a #\\tab)")).unwrap();
+        assert!(ast == program!(s!(ident!("f"), lit_bool!(true), lit_bool!(false), lit_char!('a'), lit_char!('\t'))));
+        assert!(ast.to_string() == "(f #t #f #\\a #\\tab)");
+        assert!(lit_bool!(false).is_value());
+        assert!(lit_char!('a').is_value());
+    }
+
+    #[test]
+    fn test_subst() {
+        let ast = parse(&lex("(f x [1 2] {k 'x})")).unwrap();
+        let x = Symbol::intern("x");
+        let result = ast.subst(&[x], &[lit_num!(42)]);
+        assert!(result == program!(s!(ident!("f"), lit_num!(42), vector!(lit_num!(1), lit_num!(2)),
+                                      map!(ident!("k") => quote!(lit_num!(42))))));
+
+        // The vector has nothing to replace, so it is shared.
+        let elements = |n: &Node| match n.kind {
+            NodeKind::Program(ref ns) => match ns[0].kind {
+                NodeKind::S(ref ns) => ns.clone(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        let (before, after) = (elements(&ast), elements(&result));
+        match (&before[2].kind, &after[2].kind) {
+            (&NodeKind::Vector(ref a), &NodeKind::Vector(ref b)) => assert!(Rc::ptr_eq(a, b)),
+            _ => unreachable!(),
+        }
+        // Nothing to replace at all.
+        let same = ast.subst(&[Symbol::intern("y")], &[lit_num!(42)]);
+        assert!(Rc::ptr_eq(&elements(&ast), &elements(&same)));
     }
 }
@@ -0,0 +1,313 @@
+// Pretty printing.
+//
+// A node is first turned into a Doc, which describes the possible layouts,
+// then the Doc is rendered to fit (if possible) in a given width. This is
+// Wadler's "prettier printer", with Oppen-style lookahead: a group is printed
+// on one line if it fits, including whatever follows it up to the next line
+// break, otherwise each Line in it becomes a newline.
+//
+// How `(keyword ...)` is broken over lines is chosen by the special form, see
+// forms::Layout.
+
+use parser::{Node, NodeKind};
+use forms::{self, Layout};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Doc {
+    Text(String),
+    // A space, or a newline if the enclosing group is broken.
+    Line,
+    // Lines in the doc are indented by a further n columns.
+    Nest(usize, Box<Doc>),
+    // Lines in the doc are indented to the column where the doc starts.
+    Align(Box<Doc>),
+    // Printed on one line if it fits, otherwise the Lines directly in the
+    // group (not in nested groups) are newlines.
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+pub fn text<S: Into<String>>(s: S) -> Doc {
+    Doc::Text(s.into())
+}
+
+pub fn nest(n: usize, d: Doc) -> Doc {
+    Doc::Nest(n, Box::new(d))
+}
+
+pub fn align(d: Doc) -> Doc {
+    Doc::Align(Box::new(d))
+}
+
+pub fn group(d: Doc) -> Doc {
+    Doc::Group(Box::new(d))
+}
+
+// ds separated by Lines.
+pub fn lines(ds: Vec<Doc>) -> Doc {
+    let mut result = Vec::with_capacity(ds.len() * 2);
+    for d in ds {
+        if !result.is_empty() {
+            result.push(Doc::Line);
+        }
+        result.push(d);
+    }
+    Doc::Concat(result)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+pub fn render(doc: &Doc, width: usize) -> String {
+    let mut out = String::new();
+    let mut col = 0;
+    // Docs still to print, with their indentation, the next one last.
+    let mut stack = vec![(0, Mode::Break, doc)];
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match *doc {
+            Doc::Text(ref s) => {
+                out.push_str(s);
+                col += s.chars().count();
+            }
+            Doc::Line => {
+                if mode == Mode::Flat {
+                    out.push(' ');
+                    col += 1;
+                } else {
+                    out.push('\n');
+                    for _ in 0..indent {
+                        out.push(' ');
+                    }
+                    col = indent;
+                }
+            }
+            Doc::Nest(n, ref d) => stack.push((indent + n, mode, d)),
+            Doc::Align(ref d) => stack.push((col, mode, d)),
+            Doc::Group(ref d) => {
+                let mode = if mode == Mode::Flat || fits(width as isize - col as isize, d, &stack) {
+                    Mode::Flat
+                } else {
+                    Mode::Break
+                };
+                stack.push((indent, mode, d));
+            }
+            Doc::Concat(ref ds) => {
+                for d in ds.iter().rev() {
+                    stack.push((indent, mode, d));
+                }
+            }
+        }
+    }
+    out
+}
+
+// True if doc fits in width columns when printed flat, followed by rest up to
+// its first newline.
+fn fits(width: isize, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut width = width;
+    let mut stack = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+    while width >= 0 {
+        let (mode, doc) = match stack.pop() {
+            Some(x) => x,
+            None => match rest.next() {
+                Some(&(_, mode, doc)) => (mode, doc),
+                None => return true,
+            },
+        };
+        match *doc {
+            Doc::Text(ref s) => width -= s.chars().count() as isize,
+            Doc::Line => {
+                if mode == Mode::Break {
+                    return true;
+                }
+                width -= 1;
+            }
+            Doc::Nest(_, ref d) | Doc::Align(ref d) | Doc::Group(ref d) => stack.push((mode, d)),
+            Doc::Concat(ref ds) => {
+                for d in ds.iter().rev() {
+                    stack.push((mode, d));
+                }
+            }
+        }
+    }
+    false
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Config {
+    // The maximum line length. Longer lines are only printed if there is no
+    // way to break them.
+    pub width: usize,
+    // How far the body of a form is indented.
+    pub indent: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            width: 80,
+            indent: 2,
+        }
+    }
+}
+
+// Prints node across as many lines as necessary to fit the width. Each
+// top-level form in a Program starts on a new line.
+pub fn pretty(node: &Node, config: &Config) -> String {
+    match node.kind {
+        NodeKind::Program(ref ns) => {
+            let forms: Vec<_> = ns.iter().map(|n| render(&to_doc(n, config), config.width)).collect();
+            forms.join("\n")
+        }
+        _ => render(&to_doc(node, config), config.width),
+    }
+}
+
+pub fn to_doc(node: &Node, config: &Config) -> Doc {
+    let docs = |ns: &[Node]| -> Vec<Doc> { ns.iter().map(|n| to_doc(n, config)).collect() };
+    match node.kind {
+        NodeKind::Program(ref ns) => lines(docs(ns)),
+        NodeKind::S(ref ns) => {
+            let layout = match ns.first().map(|n| &n.kind) {
+                Some(&NodeKind::Keyword(k)) => forms::lookup(k).map_or(Layout::Call, |f| f.layout()),
+                _ => Layout::Call,
+            };
+            s_doc(ns, layout, config)
+        }
+        NodeKind::Vector(ref ns) => bracket("[", lines(docs(ns)), "]"),
+        NodeKind::Map(ref ps) => {
+            let ps = ps.iter()
+                .map(|&(ref k, ref v)| pair(to_doc(k, config), to_doc(v, config), config))
+                .collect();
+            bracket("{", lines(ps), "}")
+        }
+        NodeKind::Quote(ref n) => Doc::Concat(vec![text("'"), to_doc(n, config)]),
+        NodeKind::Quasiquote(ref n) => Doc::Concat(vec![text("`"), to_doc(n, config)]),
+        NodeKind::Unquote(ref n) => Doc::Concat(vec![text(","), to_doc(n, config)]),
+        NodeKind::UnquoteSplicing(ref n) => Doc::Concat(vec![text(",@"), to_doc(n, config)]),
+        _ => text(node.to_string()),
+    }
+}
+
+// open, the aligned elements, close.
+fn bracket(open: &str, elements: Doc, close: &str) -> Doc {
+    group(Doc::Concat(vec![text(open), align(elements), text(close)]))
+}
+
+// A key and value (or name and value), the value is indented under the key if
+// they don't fit on one line.
+fn pair(k: Doc, v: Doc, config: &Config) -> Doc {
+    group(Doc::Concat(vec![k, nest(config.indent, Doc::Concat(vec![Doc::Line, v]))]))
+}
+
+fn s_doc(ns: &[Node], layout: Layout, config: &Config) -> Doc {
+    let docs = |ns: &[Node]| -> Vec<Doc> { ns.iter().map(|n| to_doc(n, config)).collect() };
+    // `(head element ... body)`, body is indented on its own line.
+    let with_body = |head: Doc, body: &Node| {
+        group(Doc::Concat(vec![text("("),
+                               head,
+                               nest(config.indent, Doc::Concat(vec![Doc::Line, to_doc(body, config)])),
+                               text(")")]))
+    };
+
+    let len = ns.len();
+    match layout {
+        Layout::Body if len > 1 => {
+            let head = docs(&ns[..len - 1]);
+            with_body(group(lines(head)), &ns[len - 1])
+        }
+        // Only if the bindings come in pairs.
+        Layout::Bindings if len > 2 && len % 2 == 0 => {
+            let bindings = ns[1..len - 1]
+                .chunks(2)
+                .map(|p| pair(to_doc(&p[0], config), to_doc(&p[1], config), config))
+                .collect();
+            let head = Doc::Concat(vec![to_doc(&ns[0], config), text(" "), align(lines(bindings))]);
+            with_body(head, &ns[len - 1])
+        }
+        _ => {
+            // The arguments are aligned with the first one, unless the head is
+            // a list, then everything is aligned.
+            match ns.first() {
+                Some(head) if len > 1 && !is_list(head) => {
+                    bracket("(",
+                            Doc::Concat(vec![to_doc(head, config), text(" "), align(lines(docs(&ns[1..])))]),
+                            ")")
+                }
+                _ => bracket("(", lines(docs(ns)), ")"),
+            }
+        }
+    }
+}
+
+fn is_list(node: &Node) -> bool {
+    match node.kind {
+        NodeKind::S(_) | NodeKind::Vector(_) | NodeKind::Map(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parser;
+    use lexer;
+
+    fn pretty_str(input: &str, width: usize) -> String {
+        let ast = parser::parse(&lexer::lex(input).unwrap()).unwrap();
+        let config = Config {
+            width: width,
+            indent: 2,
+        };
+        pretty(&ast, &config)
+    }
+
+    #[test]
+    fn test_render() {
+        let doc = group(Doc::Concat(vec![text("(f"), nest(2, Doc::Concat(vec![Doc::Line, text("a"), Doc::Line, text("b")])), text(")")]));
+        assert!(render(&doc, 80) == "(f a b)");
+        assert!(render(&doc, 7) == "(f a b)");
+        // The `)` has to fit too.
+        assert!(render(&doc, 6) == "(f\n  a\n  b)");
+        let doc = Doc::Concat(vec![text("xx "), align(lines(vec![text("a"), text("b")]))]);
+        assert!(render(&doc, 80) == "xx a\n   b");
+    }
+
+    #[test]
+    fn test_calls() {
+        assert!(pretty_str("(f a b) (g)", 80) == "(f a b)\n(g)");
+        assert!(pretty_str("(foo (bar 1 2) (baz 3 4))", 20) == "(foo (bar 1 2)\n     (baz 3 4))");
+        assert!(pretty_str("((g x) a b)", 8) == "((g x)\n a\n b)");
+        assert!(pretty_str("[1 2 3] {a 1 b 2}", 6) == "[1\n 2\n 3]\n{a 1\n b 2}");
+        assert!(pretty_str("'(a b c) `(c ,@d e)", 4) == "'(a b\n    c)\n`(c ,@d\n    e)");
+    }
+
+    #[test]
+    fn test_forms() {
+        assert!(pretty_str("(fn x y (+ x y))", 80) == "(fn x y (+ x y))");
+        assert!(pretty_str("(fn x y (+ x y))", 12) == "(fn x y\n  (+ x y))");
+        assert!(pretty_str("(macro m x `(+ ,x 1))", 16) == "(macro m x\n  `(+ ,x 1))");
+        assert!(pretty_str("(let a 1 bb (+ a 2) (+ a bb))", 20) == "(let a 1\n     bb (+ a 2)\n  (+ a bb))");
+        assert!(pretty_str("(let abc (fn x (+ x 100)) (abc 1))", 20) ==
+                "(let abc\n       (fn x\n         (+ x 100))\n  (abc 1))");
+        // Not a well-formed let.
+        assert!(pretty_str("(let a b)", 6) == "(let a\n     b)");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let input = "(let double (fn x (+ x x)) xs [1 2 {\"a\" #\\b c 4.5}] (print (double 21) 'xs `(a ,@xs) \"s\\n\"))";
+        let ast = parser::parse(&lexer::lex(input).unwrap()).unwrap();
+        for width in 0..100 {
+            let printed = pretty_str(input, width);
+            assert!(parser::parse(&lexer::lex(&printed).unwrap()).unwrap() == ast);
+            if width >= 24 {
+                assert!(printed.lines().all(|l| l.chars().count() <= width), "{}", printed);
+            }
+        }
+    }
+}
@@ -0,0 +1,712 @@
+// Structural rewriting.
+//
+// A rule file is a sequence of rules, each either
+//
+//   PATTERN => TEMPLATE   rewrites each form which matches PATTERN (see
+//                         search.rs) to TEMPLATE, with every metavariable in
+//                         TEMPLATE replaced by the form it matched.
+//   (rename OLD NEW)      renames each variable called OLD which is bound by
+//                         `let`, `fn`, or `macro`, and the references to it.
+//
+// Rules are applied in order, each to the result of the ones before. Rewrites
+// edit the source text rather than printing the tree, so layout and comments
+// outside the rewritten forms are untouched, and the forms bound to
+// metavariables are copied as they were written. Only the outermost form
+// which matches is rewritten, forms inside it are left for another run. Quoted
+// data is not rewritten, except for the unquoted parts of a quasiquote.
+//
+// Renaming is lexical: a `let` variable's scope is the values after it and
+// the body, a formal's is the body of its `fn` or `macro`. Free variables
+// called OLD, and names in quoted data, are left alone. If renaming would
+// change which variable a name refers to (e.g., NEW is used in the scope of an
+// OLD), nothing is renamed and the first such name is reported.
+
+use parser::{self, Node, NodeKind, ParseError};
+use lexer::Span;
+use search::{self, Pattern, PatternError, PatternErrorKind};
+use symbol::Symbol;
+use visit::{self, Visitor};
+use std::cmp;
+use std::fmt::{self, Write};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum RewriteErrorKind {
+    // The input couldn't be lexed or parsed.
+    Syntax(String),
+    // A rule's pattern is invalid.
+    Pattern(PatternErrorKind),
+    // A form in a rule file which isn't a rule.
+    NotARule(String),
+    // `PATTERN =>` at the end of a rule file.
+    MissingTemplate,
+    // A metavariable in a template which isn't bound by the pattern.
+    UnboundVar(Symbol),
+    // `...` in a template.
+    EllipsisInTemplate,
+    // Renaming would change which variable this name refers to.
+    Capture(Symbol),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct RewriteError {
+    pub kind: RewriteErrorKind,
+    pub span: Span,
+}
+
+impl RewriteError {
+    pub fn new(kind: RewriteErrorKind, span: Span) -> RewriteError {
+        RewriteError {
+            kind: kind,
+            span: span,
+        }
+    }
+}
+
+impl From<ParseError> for RewriteError {
+    fn from(e: ParseError) -> RewriteError {
+        RewriteError::new(RewriteErrorKind::Syntax(e.kind.to_string()), e.span)
+    }
+}
+
+impl From<PatternError> for RewriteError {
+    fn from(e: PatternError) -> RewriteError {
+        RewriteError::new(RewriteErrorKind::Pattern(e.kind), e.span)
+    }
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}: ", self.span));
+        match self.kind {
+            RewriteErrorKind::Syntax(ref msg) => msg.fmt(f),
+            RewriteErrorKind::Pattern(ref kind) => kind.fmt(f),
+            RewriteErrorKind::NotARule(ref found) => {
+                write!(f, "expected `PATTERN => TEMPLATE` or `(rename OLD NEW)`, found `{}`", found)
+            }
+            RewriteErrorKind::MissingTemplate => write!(f, "`=>` needs a template"),
+            RewriteErrorKind::UnboundVar(v) => write!(f, "`{}` is not bound by the pattern", v),
+            RewriteErrorKind::EllipsisInTemplate => write!(f, "`...` can only be used in a pattern"),
+            RewriteErrorKind::Capture(name) => write!(f, "renaming would change which variable `{}` refers to", name),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Rule {
+    Rewrite { pattern: Pattern, template: Node },
+    Rename { old: Symbol, new: Symbol },
+}
+
+// Replace the source text at span with text.
+#[derive(Clone, PartialEq, Debug)]
+struct Edit {
+    span: Span,
+    text: String,
+}
+
+pub fn parse_rules(input: &str) -> Result<Vec<Rule>, RewriteError> {
+    let program = try!(parser::parse_str(input));
+    let ns = match program.kind {
+        NodeKind::Program(ref ns) => ns,
+        _ => unreachable!(),
+    };
+    let arrow = NodeKind::Ident(Symbol::intern("=>"));
+    let mut rules = Vec::new();
+    let mut i = 0;
+    while i < ns.len() {
+        if ns.get(i + 1).map_or(false, |n| *n == arrow) {
+            let template = match ns.get(i + 2) {
+                Some(template) => template.clone(),
+                None => return Err(RewriteError::new(RewriteErrorKind::MissingTemplate, ns[i + 1].span)),
+            };
+            let pattern = try!(Pattern::from_node(ns[i].clone()));
+            try!(check_template(&pattern, &template));
+            rules.push(Rule::Rewrite {
+                pattern: pattern,
+                template: template,
+            });
+            i += 3;
+            continue;
+        }
+        let rename = match ns[i].kind {
+            NodeKind::S(ref rename) if rename.len() == 3 && rename[0] == NodeKind::Ident(Symbol::intern("rename")) => {
+                match (&rename[1].kind, &rename[2].kind) {
+                    (&NodeKind::Ident(old), &NodeKind::Ident(new)) => Some(Rule::Rename { old: old, new: new }),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match rename {
+            Some(rule) => rules.push(rule),
+            None => return Err(RewriteError::new(RewriteErrorKind::NotARule(ns[i].to_string()), ns[i].span)),
+        }
+        i += 1;
+    }
+    Ok(rules)
+}
+
+// Every metavariable in template must be bound by pattern.
+fn check_template(pattern: &Pattern, template: &Node) -> Result<(), RewriteError> {
+    struct Vars(Vec<Symbol>);
+
+    impl Visitor for Vars {
+        fn visit_ident(&mut self, s: Symbol, _span: Span) {
+            if search::is_var(s) && &*s != "?_" {
+                self.0.push(s);
+            }
+        }
+    }
+
+    struct Check<'a> {
+        bound: &'a [Symbol],
+        error: Option<RewriteError>,
+    }
+
+    impl<'a> Visitor for Check<'a> {
+        fn visit_ident(&mut self, s: Symbol, span: Span) {
+            let kind = if &*s == "..." {
+                RewriteErrorKind::EllipsisInTemplate
+            } else if search::is_var(s) && !self.bound.contains(&s) {
+                RewriteErrorKind::UnboundVar(s)
+            } else {
+                return;
+            };
+            if self.error.is_none() {
+                self.error = Some(RewriteError::new(kind, span));
+            }
+        }
+    }
+
+    let mut vars = Vars(Vec::new());
+    vars.visit_node(&pattern.node);
+    let mut check = Check {
+        bound: &vars.0,
+        error: None,
+    };
+    check.visit_node(template);
+    match check.error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Applies rules to source, in order. The result is source if no rule applies.
+pub fn apply(rules: &[Rule], source: &str) -> Result<String, RewriteError> {
+    let mut source = source.to_owned();
+    for rule in rules {
+        // Each rule sees the result of the ones before, so the spans are
+        // only good for one rule.
+        let tree = try!(parser::parse_str(&source));
+        let edits = match *rule {
+            Rule::Rewrite { ref pattern, ref template } => rewrites(pattern, template, &tree, &source),
+            Rule::Rename { old, new } => try!(rename(&tree, old, new)),
+        };
+        if !edits.is_empty() {
+            source = apply_edits(&source, &edits);
+        }
+    }
+    Ok(source)
+}
+
+// edits must be in order and not overlap.
+fn apply_edits(source: &str, edits: &[Edit]) -> String {
+    let mut result = String::with_capacity(source.len());
+    let mut pos = 0;
+    for e in edits {
+        result.push_str(&source[pos..e.span.lo]);
+        result.push_str(&e.text);
+        pos = e.span.hi;
+    }
+    result.push_str(&source[pos..]);
+    result
+}
+
+fn rewrites(pattern: &Pattern, template: &Node, tree: &Node, source: &str) -> Vec<Edit> {
+    let mut rewriter = Rewriter {
+        pattern: pattern,
+        template: template,
+        source: source,
+        edits: Vec::new(),
+    };
+    rewriter.visit_node(tree);
+    rewriter.edits
+}
+
+struct Rewriter<'a> {
+    pattern: &'a Pattern,
+    template: &'a Node,
+    source: &'a str,
+    edits: Vec<Edit>,
+}
+
+impl<'a> Visitor for Rewriter<'a> {
+    fn visit_node(&mut self, node: &Node) {
+        if let NodeKind::Program(_) = node.kind {
+            return visit::walk_node(self, node);
+        }
+        match self.pattern.matches(node) {
+            Some(bindings) => {
+                let mut text = String::new();
+                render(self.template, &bindings, self.source, &mut text);
+                self.edits.push(Edit {
+                    span: node.span,
+                    text: text,
+                });
+            }
+            None => visit::walk_node(self, node),
+        }
+    }
+
+    // Quoted data is not code.
+    fn visit_quote(&mut self, _n: &Node, _span: Span) {}
+
+    fn visit_quasiquote(&mut self, n: &Node, _span: Span) {
+        Template {
+            code: self,
+            depth: 0,
+        }.visit_node(n)
+    }
+}
+
+// Writes template to out, each metavariable is replaced by the source text
+// of the node it is bound to.
+fn render(template: &Node, bindings: &[(Symbol, Node)], source: &str, out: &mut String) {
+    let list = |open: &str, ns: &[Node], close: &str, out: &mut String| {
+        out.push_str(open);
+        for (i, n) in ns.iter().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            render(n, bindings, source, out);
+        }
+        out.push_str(close);
+    };
+    match template.kind {
+        NodeKind::Ident(v) if search::is_var(v) => {
+            // The template has been checked, so every metavariable is bound.
+            let n = &bindings.iter().find(|&&(b, _)| b == v).unwrap().1;
+            out.push_str(&source[n.span.lo..n.span.hi]);
+        }
+        NodeKind::Program(ref ns) | NodeKind::S(ref ns) => list("(", ns, ")", out),
+        NodeKind::Vector(ref ns) => list("[", ns, "]", out),
+        NodeKind::Map(ref ps) => {
+            out.push('{');
+            for (i, &(ref k, ref v)) in ps.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                render(k, bindings, source, out);
+                out.push(' ');
+                render(v, bindings, source, out);
+            }
+            out.push('}');
+        }
+        NodeKind::Quote(ref n) => {
+            out.push('\'');
+            render(n, bindings, source, out);
+        }
+        NodeKind::Quasiquote(ref n) => {
+            out.push('`');
+            render(n, bindings, source, out);
+        }
+        NodeKind::Unquote(ref n) => {
+            out.push(',');
+            render(n, bindings, source, out);
+        }
+        NodeKind::UnquoteSplicing(ref n) => {
+            out.push_str(",@");
+            render(n, bindings, source, out);
+        }
+        _ => {
+            let _ = write!(out, "{}", template);
+        }
+    }
+}
+
+fn rename(tree: &Node, old: Symbol, new: Symbol) -> Result<Vec<Edit>, RewriteError> {
+    let mut renamer = Renamer {
+        old: old,
+        new: new,
+        scope: Vec::new(),
+        edits: Vec::new(),
+        capture: None,
+    };
+    renamer.visit_node(tree);
+    match renamer.capture {
+        Some((name, span)) => Err(RewriteError::new(RewriteErrorKind::Capture(name), span)),
+        None => Ok(renamer.edits),
+    }
+}
+
+struct Renamer {
+    old: Symbol,
+    new: Symbol,
+    // The variables in scope, innermost last.
+    scope: Vec<Symbol>,
+    edits: Vec<Edit>,
+    // The first name whose meaning renaming would change.
+    capture: Option<(Symbol, Span)>,
+}
+
+impl Renamer {
+    // The index in scope of the variable name refers to, None if it is free.
+    fn lookup(&self, name: Symbol) -> Option<usize> {
+        self.scope.iter().rposition(|&s| s == name)
+    }
+
+    // The same after renaming, when the variables called old are called new.
+    fn lookup_renamed(&self, name: Symbol) -> Option<usize> {
+        self.scope.iter().rposition(|&s| if s == self.old { name == self.new } else { s == name })
+    }
+
+    fn bind(&mut self, n: &Node) {
+        if let NodeKind::Ident(s) = n.kind {
+            self.scope.push(s);
+            if s == self.old {
+                self.edit(n.span);
+            }
+        }
+    }
+
+    fn edit(&mut self, span: Span) {
+        self.edits.push(Edit {
+            span: span,
+            text: self.new.to_string(),
+        });
+    }
+
+    fn body(&mut self, mark: usize, body: &Node) {
+        self.visit_node(body);
+        self.scope.truncate(mark);
+    }
+}
+
+impl Visitor for Renamer {
+    fn visit_s(&mut self, ns: &[Node], _span: Span) {
+        let mark = self.scope.len();
+        let last = ns.len().saturating_sub(1);
+        if ns.len() < 2 {
+            visit::walk_nodes(self, ns)
+        } else if ns[0] == NodeKind::Keyword("let") {
+            for b in ns[1..last].chunks(2) {
+                if b.len() == 2 {
+                    self.visit_node(&b[1]);
+                }
+                self.bind(&b[0]);
+            }
+            self.body(mark, &ns[last])
+        } else if ns[0] == NodeKind::Keyword("fn") || ns[0] == NodeKind::Keyword("macro") {
+            // A macro's name is not a variable.
+            let formals = if ns[0] == NodeKind::Keyword("fn") { 1 } else { 2 };
+            for n in ns[cmp::min(formals, last)..last].iter() {
+                self.bind(n);
+            }
+            self.body(mark, &ns[last])
+        } else {
+            visit::walk_nodes(self, ns)
+        }
+    }
+
+    fn visit_ident(&mut self, s: Symbol, span: Span) {
+        let (old, new) = (self.old, self.new);
+        let (before, after) = if s == old && self.lookup(old).is_some() {
+            self.edit(span);
+            (self.lookup(old), self.lookup_renamed(new))
+        } else if s == new {
+            (self.lookup(new), self.lookup_renamed(new))
+        } else {
+            return;
+        };
+        if before != after && self.capture.is_none() {
+            self.capture = Some((s, span));
+        }
+    }
+
+    // Quoted data is not code.
+    fn visit_quote(&mut self, _n: &Node, _span: Span) {}
+
+    fn visit_quasiquote(&mut self, n: &Node, _span: Span) {
+        Template {
+            code: self,
+            depth: 0,
+        }.visit_node(n)
+    }
+}
+
+// Only the unquoted parts of a template are code, they are visited by code.
+// depth is the number of quasiquotes enclosing the node being visited, not
+// counting the outermost one.
+struct Template<'a, V: 'a> {
+    code: &'a mut V,
+    depth: usize,
+}
+
+impl<'a, V: Visitor> Template<'a, V> {
+    fn unquoted(&mut self, n: &Node) {
+        if self.depth == 0 {
+            self.code.visit_node(n);
+        } else {
+            self.depth -= 1;
+            self.visit_node(n);
+            self.depth += 1;
+        }
+    }
+}
+
+impl<'a, V: Visitor> Visitor for Template<'a, V> {
+    fn visit_quasiquote(&mut self, n: &Node, _span: Span) {
+        self.depth += 1;
+        self.visit_node(n);
+        self.depth -= 1;
+    }
+
+    fn visit_unquote(&mut self, n: &Node, _span: Span) {
+        self.unquoted(n)
+    }
+
+    fn visit_unquote_splicing(&mut self, n: &Node, _span: Span) {
+        self.unquoted(n)
+    }
+}
+
+// Lines of context around each change in a diff.
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+// A unified diff from old to new, which are the contents of the file name.
+// Empty if they are the same.
+pub fn diff(name: &str, old: &str, new: &str) -> String {
+    if old == new {
+        return String::new();
+    }
+    let a: Vec<_> = old.split_inclusive('\n').collect();
+    let b: Vec<_> = new.split_inclusive('\n').collect();
+    let lines = diff_lines(&a, &b);
+    let changes: Vec<_> = (0..lines.len()).filter(|&i| !is_same(lines[i])).collect();
+
+    let mut out = format!("--- {}\n+++ {}\n", name, name);
+    let mut i = 0;
+    while i < changes.len() {
+        // Changes which are close enough for their context to overlap go in
+        // the same hunk.
+        let mut j = i;
+        while j + 1 < changes.len() && changes[j + 1] - changes[j] <= 2 * CONTEXT + 1 {
+            j += 1;
+        }
+        let lo = changes[i].saturating_sub(CONTEXT);
+        let hi = cmp::min(lines.len(), changes[j] + CONTEXT + 1);
+        let (old_before, new_before) = counts(&lines[..lo]);
+        let (old_len, new_len) = counts(&lines[lo..hi]);
+        // An empty range is given as the line before it.
+        let start = |before: usize, len: usize| if len == 0 { before } else { before + 1 };
+        let _ = write!(out,
+                       "@@ -{},{} +{},{} @@\n",
+                       start(old_before, old_len),
+                       old_len,
+                       start(new_before, new_len),
+                       new_len);
+        for line in &lines[lo..hi] {
+            let (prefix, text) = match *line {
+                Line::Same(text) => (' ', text),
+                Line::Removed(text) => ('-', text),
+                Line::Added(text) => ('+', text),
+            };
+            out.push(prefix);
+            out.push_str(text);
+            if !text.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+        i = j + 1;
+    }
+    out
+}
+
+fn is_same(line: Line) -> bool {
+    match line {
+        Line::Same(_) => true,
+        _ => false,
+    }
+}
+
+// The number of old and new lines in lines.
+fn counts(lines: &[Line]) -> (usize, usize) {
+    lines.iter().fold((0, 0), |(old, new), line| {
+        match *line {
+            Line::Same(_) => (old + 1, new + 1),
+            Line::Removed(_) => (old + 1, new),
+            Line::Added(_) => (old, new + 1),
+        }
+    })
+}
+
+// The shortest edit from a to b, using Myers' algorithm.
+fn diff_lines<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<Line<'a>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    // v[k + offset] is the furthest x reached on diagonal k = x - y.
+    let offset = max + 1;
+    let mut v = vec![0; 2 * max as usize + 3];
+    // v before each round, to find the way back.
+    let mut trace = Vec::new();
+    'search: for d in 0..max + 1 {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+            k += 2;
+        }
+    }
+
+    let mut result = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let i = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            result.push(Line::Same(a[x as usize - 1]));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                result.push(Line::Added(b[y as usize - 1]));
+            } else {
+                result.push(Line::Removed(a[x as usize - 1]));
+            }
+            x = prev_x;
+            y = prev_y;
+        }
+    }
+    result.reverse();
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn apply_str(rules: &str, source: &str) -> Result<String, RewriteError> {
+        apply(&parse_rules(rules).unwrap(), source)
+    }
+
+    fn rule_error(rules: &str) -> RewriteErrorKind {
+        parse_rules(rules).unwrap_err().kind
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules = parse_rules("; simplify\n(+ ?x 0) => ?x\n(rename a b)\n[?x ...] => ?x").unwrap();
+        assert!(rules.len() == 3);
+        match rules[1] {
+            Rule::Rename { old, new } => assert!(old == Symbol::intern("a") && new == Symbol::intern("b")),
+            _ => panic!("{:?}", rules[1]),
+        }
+
+        assert!(rule_error("(+ ?x 0) =>") == RewriteErrorKind::MissingTemplate);
+        assert!(rule_error("(+ ?x 0) ?x") == RewriteErrorKind::NotARule("(+ ?x 0)".to_owned()));
+        assert!(rule_error("(rename a)") == RewriteErrorKind::NotARule("(rename a)".to_owned()));
+        assert!(rule_error("(+ ?x 0) => ?y") == RewriteErrorKind::UnboundVar(Symbol::intern("?y")));
+        assert!(rule_error("(+ ?_ 0) => ?_") == RewriteErrorKind::UnboundVar(Symbol::intern("?_")));
+        assert!(rule_error("(+ ?x ...) => (+ ...)") == RewriteErrorKind::EllipsisInTemplate);
+        assert!(rule_error("... => 1") == RewriteErrorKind::Pattern(PatternErrorKind::MisplacedEllipsis));
+        let e = parse_rules("(+ ?x 0) => ?x\n(f ?y) => (g ?z)").unwrap_err();
+        assert!(e.to_string() == "2:14: `?z` is not bound by the pattern");
+    }
+
+    #[test]
+    fn test_rewrite() {
+        // Layout and comments outside the rewrites are kept, and the bound
+        // forms are copied as written.
+        let source = "; adds\n(print (+ (f  x ; the x\n          1) 0)\n       (+ 2 3))\n";
+        assert!(apply_str("(+ ?x 0) => ?x", source).unwrap() ==
+                "; adds\n(print (f  x ; the x\n          1)\n       (+ 2 3))\n");
+        assert!(apply_str("(+ ?x ?y) => (+ ?y ?x)", source).unwrap() ==
+                "; adds\n(print (+ 0 (f  x ; the x\n          1))\n       (+ 3 2))\n");
+        // Only the outermost match is rewritten.
+        assert!(apply_str("(+ ?x 0) => ?x", "(+ (+ a 0) 0)").unwrap() == "(+ a 0)");
+        // Quoted data is left alone, apart from the unquoted parts of a
+        // quasiquote.
+        assert!(apply_str("(+ ?x 0) => ?x", "(print '(+ b 0))").unwrap() == "(print '(+ b 0))");
+        assert!(apply_str("(+ ?x 0) => ?x", "(print `(+ b 0))").unwrap() == "(print `(+ b 0))");
+        assert!(apply_str("(+ ?x 0) => ?x", "`((+ b 0) ,(+ c 0) `,(+ d 0) ',(+ e 0))").unwrap() ==
+                "`((+ b 0) ,c `,(+ d 0) ',e)");
+        // Templates may build any kind of node.
+        assert!(apply_str("(pair ?a ?b) => {'a [?a] \"b\" `(,?b)}", "(pair 1 (g))").unwrap() ==
+                "{'a [1] \"b\" `(,(g))}");
+        // Each rule sees the result of the ones before.
+        assert!(apply_str("(inc ?x) => (+ ?x 1)\n(+ ?x 1) => (succ ?x)", "(inc y)").unwrap() == "(succ y)");
+        assert!(apply_str("(inc ?x) => (+ ?x 1)", "(dec y)").unwrap() == "(dec y)");
+        assert!(apply_str("(inc ?x) => (+ ?x 1)", "(inc").unwrap_err().kind ==
+                RewriteErrorKind::Syntax("expected `)` to close `(` at 1:1".to_owned()));
+    }
+
+    #[test]
+    fn test_rename() {
+        let rename = |source: &str| apply_str("(rename x y)", source);
+        assert!(rename("(let x 1 (+ x 2))").unwrap() == "(let y 1 (+ y 2))");
+        assert!(rename("(fn a x\n  ; x\n  (+ a x))").unwrap() == "(fn a y\n  ; x\n  (+ a y))");
+        // Free variables and quoted names are left alone.
+        assert!(rename("(+ x (let x 1 '(x ,x)) `(x ,x))").unwrap() == "(+ x (let y 1 '(x ,x)) `(x ,x))");
+        assert!(rename("(let x 1 `(x ,x ``,,x))").unwrap() == "(let y 1 `(x ,y ``,,x))");
+        // A value is not in the scope of its own variable, later ones are.
+        assert!(rename("(let x x z x (+ x z))").unwrap() == "(let y x z y (+ y z))");
+        // Macro formals are renamed, not macro names.
+        assert!(rename("(macro x x `(+ ,x 1))").unwrap() == "(macro x y `(+ ,y 1))");
+        // Shadowing by another name is fine.
+        assert!(rename("(let x 1 (fn z (+ x z)))").unwrap() == "(let y 1 (fn z (+ y z)))");
+
+        // The new name would capture a free variable, or be shadowed.
+        let e = rename("(let x 1 (+ x y))").unwrap_err();
+        assert!(e.kind == RewriteErrorKind::Capture(Symbol::intern("y")));
+        assert!(e.to_string() == "1:15: renaming would change which variable `y` refers to");
+        assert!(rename("(let x 1 (fn y (+ x y)))").unwrap_err().kind == RewriteErrorKind::Capture(Symbol::intern("x")));
+        assert!(rename("(let y 1 (let x 2 y))").unwrap_err().kind == RewriteErrorKind::Capture(Symbol::intern("y")));
+        // Unless it isn't used.
+        assert!(rename("(let y 1 (let x 2 x))").unwrap() == "(let y 1 (let y 2 y))");
+    }
+
+    #[test]
+    fn test_diff() {
+        assert!(diff("f.s", "a\n", "a\n") == "");
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\n";
+        assert!(diff("f.s", old, new) == "--- f.s\n+++ f.s\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n");
+        // Changes close together share a hunk, and the rest get their own.
+        let new = "1\ntwo\n2\n3\n4\n5\n6\nseven\n8\n9\n10\n11\n12\n13\n14\n15\n";
+        assert!(diff("f.s", old, new) ==
+                "--- f.s\n+++ f.s\n@@ -1,10 +1,11 @@\n 1\n+two\n 2\n 3\n 4\n 5\n 6\n-7\n+seven\n 8\n 9\n 10\n\
+                 @@ -13,4 +14,3 @@\n 13\n 14\n 15\n-16\n");
+        assert!(diff("f.s", "a", "b\n") == "--- f.s\n+++ f.s\n@@ -1,1 +1,1 @@\n-a\n\\ No newline at end of file\n+b\n");
+        assert!(diff("f.s", "", "b\n") == "--- f.s\n+++ f.s\n@@ -0,0 +1,1 @@\n+b\n");
+    }
+}
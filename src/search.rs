@@ -0,0 +1,333 @@
+// Structural search.
+//
+// A pattern is a single S form which is matched against the tree rather than
+// the text, so it is not fooled by layout or comments and understands nesting.
+// In a pattern:
+//
+//   ?name   matches any one node. If the same metavariable appears more than
+//           once, each occurrence must match an equal node (spans are
+//           ignored). `?_` matches anything and is not bound.
+//   ...     in a list or vector, matches any number of elements (including
+//           none).
+//
+// Everything else matches only itself. For example, `(+ ?x 0)` matches
+// `(+ (f y) 0)` with ?x bound to `(f y)`, and `(let ?name (fn ...) ...)`
+// matches every `let` which binds a function.
+
+use parser::{self, Node, NodeKind, ParseError};
+use lexer::Span;
+use symbol::Symbol;
+use visit::{self, Visitor};
+use std::fmt;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum PatternErrorKind {
+    // The pattern couldn't be lexed or parsed.
+    Syntax(String),
+    // The pattern has no form.
+    Empty,
+    // The pattern has more than one form, includes the first extra one.
+    ExtraForm(String),
+    // `...` somewhere other than in a list or vector.
+    MisplacedEllipsis,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct PatternError {
+    pub kind: PatternErrorKind,
+    pub span: Span,
+}
+
+impl PatternError {
+    pub fn new(kind: PatternErrorKind, span: Span) -> PatternError {
+        PatternError {
+            kind: kind,
+            span: span,
+        }
+    }
+}
+
+impl From<ParseError> for PatternError {
+    fn from(e: ParseError) -> PatternError {
+        PatternError::new(PatternErrorKind::Syntax(e.kind.to_string()), e.span)
+    }
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)
+    }
+}
+
+impl fmt::Display for PatternErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatternErrorKind::Syntax(ref msg) => msg.fmt(f),
+            PatternErrorKind::Empty => write!(f, "the pattern is empty"),
+            PatternErrorKind::ExtraForm(ref found) => write!(f, "unexpected `{}` after the pattern", found),
+            PatternErrorKind::MisplacedEllipsis => write!(f, "`...` can only be used in a list or vector"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    pub node: Node,
+}
+
+// A node which matched a pattern. bindings are the nodes each metavariable
+// matched (by the metavariable, including the `?`), in the order they first
+// appear in the pattern.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Match {
+    pub node: Node,
+    pub bindings: Vec<(Symbol, Node)>,
+}
+
+impl Match {
+    pub fn get(&self, var: Symbol) -> Option<&Node> {
+        self.bindings.iter().find(|&&(v, _)| v == var).map(|&(_, ref n)| n)
+    }
+}
+
+impl Pattern {
+    // The input must be exactly one form (comments are allowed).
+    pub fn parse(input: &str) -> Result<Pattern, PatternError> {
+        let program = try!(parser::parse_str(input));
+        let ns = match program.kind {
+            NodeKind::Program(ns) => parser::unshare(ns),
+            _ => unreachable!(),
+        };
+        if ns.len() > 1 {
+            return Err(PatternError::new(PatternErrorKind::ExtraForm(ns[1].to_string()), ns[1].span));
+        }
+        let node = match ns.into_iter().next() {
+            Some(node) => node,
+            None => return Err(PatternError::new(PatternErrorKind::Empty, program.span)),
+        };
+        Pattern::from_node(node)
+    }
+
+    pub fn from_node(node: Node) -> Result<Pattern, PatternError> {
+        let mut check = CheckEllipses(None);
+        check.visit_node(&node);
+        match check.0 {
+            Some(span) => Err(PatternError::new(PatternErrorKind::MisplacedEllipsis, span)),
+            None => Ok(Pattern { node: node }),
+        }
+    }
+
+    // The bindings if node matches the pattern.
+    pub fn matches(&self, node: &Node) -> Option<Vec<(Symbol, Node)>> {
+        let mut bindings = Vec::new();
+        if match_node(&self.node, node, &mut bindings) {
+            Some(bindings)
+        } else {
+            None
+        }
+    }
+
+    // Every node in tree which matches the pattern, outermost first and in the
+    // order they occur. Nodes inside a match are searched too.
+    pub fn search(&self, tree: &Node) -> Vec<Match> {
+        let mut search = Search {
+            pattern: self,
+            found: Vec::new(),
+        };
+        search.visit_node(tree);
+        search.found
+    }
+}
+
+struct Search<'a> {
+    pattern: &'a Pattern,
+    found: Vec<Match>,
+}
+
+impl<'a> Visitor for Search<'a> {
+    fn visit_node(&mut self, node: &Node) {
+        // A program is not a form, e.g., `?x` only matches its forms.
+        if let NodeKind::Program(_) = node.kind {
+            return visit::walk_node(self, node);
+        }
+        if let Some(bindings) = self.pattern.matches(node) {
+            self.found.push(Match {
+                node: node.clone(),
+                bindings: bindings,
+            });
+        }
+        visit::walk_node(self, node)
+    }
+}
+
+// Finds the first `...` which isn't an element of a list or vector.
+struct CheckEllipses(Option<Span>);
+
+impl CheckEllipses {
+    fn elements(&mut self, ns: &[Node]) {
+        for n in ns {
+            if !is_ellipsis(n) {
+                self.visit_node(n);
+            }
+        }
+    }
+}
+
+impl Visitor for CheckEllipses {
+    fn visit_s(&mut self, ns: &[Node], _span: Span) {
+        self.elements(ns)
+    }
+
+    fn visit_vector(&mut self, ns: &[Node], _span: Span) {
+        self.elements(ns)
+    }
+
+    fn visit_ident(&mut self, s: Symbol, span: Span) {
+        if &*s == "..." && self.0.is_none() {
+            self.0 = Some(span);
+        }
+    }
+}
+
+fn is_ellipsis(n: &Node) -> bool {
+    match n.kind {
+        NodeKind::Ident(s) => &*s == "...",
+        _ => false,
+    }
+}
+
+// True if s is a metavariable, i.e., `?` followed by a name.
+pub fn is_var(s: Symbol) -> bool {
+    s.len() > 1 && s.starts_with('?')
+}
+
+// Adds pat's bindings to bindings if node matches. If it doesn't, some
+// bindings may have been added anyway.
+fn match_node(pat: &Node, node: &Node, bindings: &mut Vec<(Symbol, Node)>) -> bool {
+    match (&pat.kind, &node.kind) {
+        (&NodeKind::Ident(v), _) if is_var(v) => {
+            if &*v == "?_" {
+                return true;
+            }
+            if let Some(&(_, ref bound)) = bindings.iter().find(|&&(b, _)| b == v) {
+                return bound == node;
+            }
+            bindings.push((v, node.clone()));
+            true
+        }
+        (&NodeKind::S(ref ps), &NodeKind::S(ref ns)) |
+        (&NodeKind::Vector(ref ps), &NodeKind::Vector(ref ns)) => match_seq(ps, ns, bindings),
+        (&NodeKind::Map(ref ps), &NodeKind::Map(ref ns)) => {
+            ps.len() == ns.len() &&
+            ps.iter().zip(ns.iter()).all(|(&(ref pk, ref pv), &(ref k, ref v))| {
+                match_node(pk, k, bindings) && match_node(pv, v, bindings)
+            })
+        }
+        (&NodeKind::Quote(ref p), &NodeKind::Quote(ref n)) |
+        (&NodeKind::Quasiquote(ref p), &NodeKind::Quasiquote(ref n)) |
+        (&NodeKind::Unquote(ref p), &NodeKind::Unquote(ref n)) |
+        (&NodeKind::UnquoteSplicing(ref p), &NodeKind::UnquoteSplicing(ref n)) => match_node(p, n, bindings),
+        (p, n) => p == n,
+    }
+}
+
+// A `...` tries the shortest run of elements first, backtracking (and undoing
+// any bindings) if the rest of the pattern doesn't match.
+fn match_seq(ps: &[Node], ns: &[Node], bindings: &mut Vec<(Symbol, Node)>) -> bool {
+    let p = match ps.first() {
+        Some(p) => p,
+        None => return ns.is_empty(),
+    };
+    if is_ellipsis(p) {
+        for i in 0..ns.len() + 1 {
+            let len = bindings.len();
+            if match_seq(&ps[1..], &ns[i..], bindings) {
+                return true;
+            }
+            bindings.truncate(len);
+        }
+        return false;
+    }
+    !ns.is_empty() && match_node(p, &ns[0], bindings) && match_seq(&ps[1..], &ns[1..], bindings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(input: &str) -> Node {
+        parser::parse_str(input).unwrap()
+    }
+
+    fn matches(pattern: &str, input: &str) -> Option<Vec<(String, String)>> {
+        let node = parse(input);
+        let node = match node.kind {
+            NodeKind::Program(ref ns) => ns[0].clone(),
+            _ => unreachable!(),
+        };
+        Pattern::parse(pattern).unwrap().matches(&node).map(|bs| {
+            bs.into_iter().map(|(v, n)| (v.to_string(), n.to_string())).collect()
+        })
+    }
+
+    fn bindings(bs: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(bs.iter().map(|&(v, n)| (v.to_owned(), n.to_owned())).collect())
+    }
+
+    #[test]
+    fn test_match() {
+        assert!(matches("(+ ?x 0)", "(+ (f y) 0)") == bindings(&[("?x", "(f y)")]));
+        assert!(matches("(+ ?x 0)", "(+ 1 2)") == None);
+        assert!(matches("(+ ?x 0)", "(+ 1 0 0)") == None);
+        // Repeated metavariables must match equal nodes, `?_` matches anything.
+        assert!(matches("(- ?x ?x)", "(- (f 1) (f 1))") == bindings(&[("?x", "(f 1)")]));
+        assert!(matches("(- ?x ?x)", "(- (f 1) (f 2))") == None);
+        assert!(matches("(- ?_ ?_)", "(- (f 1) (f 2))") == bindings(&[]));
+        // Ellipses.
+        assert!(matches("(let ?name (fn ...) ...)", "(let f (fn x (+ x 1)) (f 2))") == bindings(&[("?name", "f")]));
+        assert!(matches("(let ?name (fn ...) ...)", "(let f 1 (f 2))") == None);
+        assert!(matches("(print ... ?last)", "(print 1 2 3)") == bindings(&[("?last", "3")]));
+        assert!(matches("(print ... ?last)", "(print)") == None);
+        assert!(matches("[... 2 ?next ...]", "[1 2 3 2 4]") == bindings(&[("?next", "3")]));
+        assert!(matches("[... ?x ?x ...]", "[1 2 3 3 4]") == bindings(&[("?x", "3")]));
+        assert!(matches("[...]", "[]") == bindings(&[]));
+        // Maps, quotes, and literals.
+        assert!(matches("{\"k\" ?v}", "{\"k\" [1]}") == bindings(&[("?v", "[1]")]));
+        assert!(matches("'(a ?x)", "'(a b)") == bindings(&[("?x", "b")]));
+        assert!(matches("'(a ?x)", "`(a b)") == None);
+        assert!(matches("#\\a", "#\\a") == bindings(&[]));
+        // `?` on its own is just a name.
+        assert!(matches("?", "?") == bindings(&[]));
+        assert!(matches("?", "x") == None);
+    }
+
+    #[test]
+    fn test_search() {
+        let tree = parse("(+ (+ a 0) 0)\n(print '(+ b 0) (- (+ 1 2) 0))");
+        let found = Pattern::parse("(+ ?x 0)").unwrap().search(&tree);
+        let found: Vec<_> = found.iter().map(|m| (m.node.span.to_string(), m.get(Symbol::intern("?x")).unwrap().to_string())).collect();
+        assert!(found ==
+                vec![("1:1".to_owned(), "(+ a 0)".to_owned()),
+                     ("1:4".to_owned(), "a".to_owned()),
+                     ("2:9".to_owned(), "b".to_owned())]);
+        // The program itself is not a match.
+        assert!(Pattern::parse("?x").unwrap().search(&parse("a b")).len() == 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        let kind = |input: &str| Pattern::parse(input).unwrap_err().kind;
+        assert!(kind("") == PatternErrorKind::Empty);
+        assert!(kind("; just a comment") == PatternErrorKind::Empty);
+        assert!(kind("(+ ?x 0) ?y") == PatternErrorKind::ExtraForm("?y".to_owned()));
+        assert!(kind("...") == PatternErrorKind::MisplacedEllipsis);
+        assert!(kind("'...") == PatternErrorKind::MisplacedEllipsis);
+        assert!(kind("{... 1}") == PatternErrorKind::MisplacedEllipsis);
+        assert!(Pattern::parse("(f (g ...) '(...))").is_ok());
+        match kind("(+ ?x") {
+            PatternErrorKind::Syntax(_) => {}
+            k => panic!("{:?}", k),
+        }
+        assert!(Pattern::parse("(+ ?x 0) ?y").unwrap_err().to_string() == "1:10: unexpected `?y` after the pattern");
+    }
+}
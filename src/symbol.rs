@@ -0,0 +1,104 @@
+// Interned symbols.
+//
+// Identifiers are represented by a Symbol, an index into a symbol table, so
+// they are cheap to copy and comparing or hashing them doesn't look at the
+// text. Each distinct string is interned once and lives until the end of the
+// program.
+//
+// Like the special form registry, the symbol table is per-thread. A Symbol
+// can only be used on the thread where it was interned, so it is neither Send
+// nor Sync.
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32, PhantomData<*const ()>);
+
+struct Interner {
+    names: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner {
+        names: HashMap::new(),
+        strings: Vec::new(),
+    });
+}
+
+impl Symbol {
+    // Returns the symbol for s, adding s to the symbol table if necessary.
+    pub fn intern(s: &str) -> Symbol {
+        INTERNER.with(|i| {
+            let mut i = i.borrow_mut();
+            if let Some(&sym) = i.names.get(s) {
+                return sym;
+            }
+            let s: &'static str = Box::leak(s.to_owned().into_boxed_str());
+            let sym = Symbol(i.strings.len() as u32, PhantomData);
+            i.strings.push(s);
+            i.names.insert(s, sym);
+            sym
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        INTERNER.with(|i| i.borrow().strings[self.0 as usize])
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Shows the text, not the index.
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+// Serialized as the text.
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Symbol, D::Error> {
+        let s = try!(String::deserialize(d));
+        Ok(Symbol::intern(&s))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let a = Symbol::intern("a");
+        assert!(a == Symbol::intern("a"));
+        assert!(a != Symbol::intern("b"));
+        assert!(a.as_str() == "a");
+        assert!(&*Symbol::intern("\u{e9}t\u{e9}") == "\u{e9}t\u{e9}");
+        assert!(Symbol::intern("") != a);
+        assert!(format!("{} {:?}", a, a) == "a \"a\"");
+    }
+}
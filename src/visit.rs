@@ -0,0 +1,310 @@
+// Visitors.
+//
+// A Visitor walks a tree by reference, a VisitorMut by mutable reference. Each
+// has a method for every kind of node, by default the method visits the node's
+// children (using the walk functions), so a visitor only overrides the methods
+// for the nodes it is interested in. An overriding method can call the walk
+// function to carry on into the children, or not to skip them.
+//
+// visit_node is called for every node and dispatches to the method for its
+// kind. Override it to see (or, for VisitorMut, replace) whole nodes. As in
+// expand::Folder, lists are passed as their elements and the span of the whole
+// list.
+//
+// Lists are shared (see parser::NodeKind), so VisitorMut works copy on write:
+// the walk functions only copy a list if it is shared and one of its elements
+// was changed. A method which changes a list itself should do the same, using
+// Rc::make_mut.
+
+use parser::{Node, NodeKind};
+use lexer::Span;
+use forms;
+use symbol::Symbol;
+use std::rc::Rc;
+
+pub trait Visitor {
+    fn visit_node(&mut self, node: &Node) {
+        walk_node(self, node)
+    }
+
+    fn visit_program(&mut self, ns: &[Node], _span: Span) {
+        walk_nodes(self, ns)
+    }
+
+    fn visit_s(&mut self, ns: &[Node], _span: Span) {
+        walk_nodes(self, ns)
+    }
+
+    fn visit_vector(&mut self, ns: &[Node], _span: Span) {
+        walk_nodes(self, ns)
+    }
+
+    fn visit_map(&mut self, ps: &[(Node, Node)], _span: Span) {
+        walk_pairs(self, ps)
+    }
+
+    // n is the quoted node, span is the span of the quote (including the `'`).
+    fn visit_quote(&mut self, n: &Node, _span: Span) {
+        self.visit_node(n)
+    }
+
+    fn visit_quasiquote(&mut self, n: &Node, _span: Span) {
+        self.visit_node(n)
+    }
+
+    fn visit_unquote(&mut self, n: &Node, _span: Span) {
+        self.visit_node(n)
+    }
+
+    fn visit_unquote_splicing(&mut self, n: &Node, _span: Span) {
+        self.visit_node(n)
+    }
+
+    fn visit_keyword(&mut self, _k: forms::Keyword, _span: Span) {}
+
+    fn visit_ident(&mut self, _s: Symbol, _span: Span) {}
+
+    // A number, boolean, char, or string.
+    fn visit_lit(&mut self, _node: &Node) {}
+}
+
+pub fn walk_node<V: Visitor + ?Sized>(v: &mut V, node: &Node) {
+    let span = node.span;
+    match node.kind {
+        NodeKind::Program(ref ns) => v.visit_program(ns, span),
+        NodeKind::S(ref ns) => v.visit_s(ns, span),
+        NodeKind::Vector(ref ns) => v.visit_vector(ns, span),
+        NodeKind::Map(ref ps) => v.visit_map(ps, span),
+        NodeKind::Quote(ref n) => v.visit_quote(n, span),
+        NodeKind::Quasiquote(ref n) => v.visit_quasiquote(n, span),
+        NodeKind::Unquote(ref n) => v.visit_unquote(n, span),
+        NodeKind::UnquoteSplicing(ref n) => v.visit_unquote_splicing(n, span),
+        NodeKind::Keyword(k) => v.visit_keyword(k, span),
+        NodeKind::Ident(s) => v.visit_ident(s, span),
+        NodeKind::LitNum(_) |
+        NodeKind::LitFloat(_) |
+        NodeKind::LitBool(_) |
+        NodeKind::LitChar(_) |
+        NodeKind::LitStr(_) => v.visit_lit(node),
+    }
+}
+
+pub fn walk_nodes<V: Visitor + ?Sized>(v: &mut V, ns: &[Node]) {
+    for n in ns {
+        v.visit_node(n);
+    }
+}
+
+pub fn walk_pairs<V: Visitor + ?Sized>(v: &mut V, ps: &[(Node, Node)]) {
+    for &(ref k, ref val) in ps {
+        v.visit_node(k);
+        v.visit_node(val);
+    }
+}
+
+pub trait VisitorMut {
+    fn visit_node(&mut self, node: &mut Node) {
+        walk_node_mut(self, node)
+    }
+
+    fn visit_program(&mut self, ns: &mut Rc<Vec<Node>>, _span: Span) {
+        walk_nodes_mut(self, ns)
+    }
+
+    fn visit_s(&mut self, ns: &mut Rc<Vec<Node>>, _span: Span) {
+        walk_nodes_mut(self, ns)
+    }
+
+    fn visit_vector(&mut self, ns: &mut Rc<Vec<Node>>, _span: Span) {
+        walk_nodes_mut(self, ns)
+    }
+
+    fn visit_map(&mut self, ps: &mut Rc<Vec<(Node, Node)>>, _span: Span) {
+        walk_pairs_mut(self, ps)
+    }
+
+    fn visit_quote(&mut self, n: &mut Rc<Node>, _span: Span) {
+        walk_rc_mut(self, n)
+    }
+
+    fn visit_quasiquote(&mut self, n: &mut Rc<Node>, _span: Span) {
+        walk_rc_mut(self, n)
+    }
+
+    fn visit_unquote(&mut self, n: &mut Rc<Node>, _span: Span) {
+        walk_rc_mut(self, n)
+    }
+
+    fn visit_unquote_splicing(&mut self, n: &mut Rc<Node>, _span: Span) {
+        walk_rc_mut(self, n)
+    }
+
+    fn visit_keyword(&mut self, _k: &mut forms::Keyword, _span: Span) {}
+
+    fn visit_ident(&mut self, _s: &mut Symbol, _span: Span) {}
+
+    fn visit_lit(&mut self, _node: &mut Node) {}
+}
+
+pub fn walk_node_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Node) {
+    let span = node.span;
+    match node.kind {
+        NodeKind::Program(ref mut ns) => return v.visit_program(ns, span),
+        NodeKind::S(ref mut ns) => return v.visit_s(ns, span),
+        NodeKind::Vector(ref mut ns) => return v.visit_vector(ns, span),
+        NodeKind::Map(ref mut ps) => return v.visit_map(ps, span),
+        NodeKind::Quote(ref mut n) => return v.visit_quote(n, span),
+        NodeKind::Quasiquote(ref mut n) => return v.visit_quasiquote(n, span),
+        NodeKind::Unquote(ref mut n) => return v.visit_unquote(n, span),
+        NodeKind::UnquoteSplicing(ref mut n) => return v.visit_unquote_splicing(n, span),
+        NodeKind::Keyword(ref mut k) => return v.visit_keyword(k, span),
+        NodeKind::Ident(ref mut s) => return v.visit_ident(s, span),
+        NodeKind::LitNum(_) |
+        NodeKind::LitFloat(_) |
+        NodeKind::LitBool(_) |
+        NodeKind::LitChar(_) |
+        NodeKind::LitStr(_) => {}
+    }
+    v.visit_lit(node)
+}
+
+// Visits each node in ns, ns is only copied if it is shared and a node changes.
+pub fn walk_nodes_mut<V: VisitorMut + ?Sized>(v: &mut V, ns: &mut Rc<Vec<Node>>) {
+    for i in 0..ns.len() {
+        // Once ns is not shared (e.g., it has been copied), there is no need
+        // to check for changes.
+        if let Some(ns) = Rc::get_mut(ns) {
+            v.visit_node(&mut ns[i]);
+            continue;
+        }
+        let mut n = ns[i].clone();
+        v.visit_node(&mut n);
+        if !same(&n, &ns[i]) {
+            Rc::make_mut(ns)[i] = n;
+        }
+    }
+}
+
+pub fn walk_pairs_mut<V: VisitorMut + ?Sized>(v: &mut V, ps: &mut Rc<Vec<(Node, Node)>>) {
+    for i in 0..ps.len() {
+        if let Some(ps) = Rc::get_mut(ps) {
+            v.visit_node(&mut ps[i].0);
+            v.visit_node(&mut ps[i].1);
+            continue;
+        }
+        let (mut k, mut val) = ps[i].clone();
+        v.visit_node(&mut k);
+        v.visit_node(&mut val);
+        if !same(&k, &ps[i].0) || !same(&val, &ps[i].1) {
+            Rc::make_mut(ps)[i] = (k, val);
+        }
+    }
+}
+
+pub fn walk_rc_mut<V: VisitorMut + ?Sized>(v: &mut V, n: &mut Rc<Node>) {
+    let mut new = (**n).clone();
+    v.visit_node(&mut new);
+    if !same(&new, n) {
+        *n = Rc::new(new);
+    }
+}
+
+// True if a and b are the same node, i.e., b is a clone of a which hasn't been
+// changed. Children are compared by pointer, so this doesn't walk the trees.
+fn same(a: &Node, b: &Node) -> bool {
+    if a.span != b.span {
+        return false;
+    }
+    match (&a.kind, &b.kind) {
+        (&NodeKind::Program(ref a), &NodeKind::Program(ref b)) |
+        (&NodeKind::S(ref a), &NodeKind::S(ref b)) |
+        (&NodeKind::Vector(ref a), &NodeKind::Vector(ref b)) => Rc::ptr_eq(a, b),
+        (&NodeKind::Map(ref a), &NodeKind::Map(ref b)) => Rc::ptr_eq(a, b),
+        (&NodeKind::Quote(ref a), &NodeKind::Quote(ref b)) |
+        (&NodeKind::Quasiquote(ref a), &NodeKind::Quasiquote(ref b)) |
+        (&NodeKind::Unquote(ref a), &NodeKind::Unquote(ref b)) |
+        (&NodeKind::UnquoteSplicing(ref a), &NodeKind::UnquoteSplicing(ref b)) => Rc::ptr_eq(a, b),
+        (a, b) => a == b,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parser;
+    use lexer;
+
+    fn parse(input: &str) -> Node {
+        parser::parse(&lexer::lex(input).unwrap()).unwrap()
+    }
+
+    // A lint: `(+ x)` with a single argument is just `x`.
+    struct PlusOne {
+        found: Vec<Span>,
+    }
+
+    impl Visitor for PlusOne {
+        fn visit_s(&mut self, ns: &[Node], span: Span) {
+            if ns.len() == 2 && ns[0] == NodeKind::Keyword("+") {
+                self.found.push(span);
+            }
+            walk_nodes(self, ns)
+        }
+
+        // Quoted data is not code.
+        fn visit_quote(&mut self, _n: &Node, _span: Span) {}
+    }
+
+    // Renames identifiers.
+    struct Rename(Symbol, Symbol);
+
+    impl VisitorMut for Rename {
+        fn visit_ident(&mut self, s: &mut Symbol, _span: Span) {
+            if *s == self.0 {
+                *s = self.1;
+            }
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let ast = parse("(+ 1 (+ 2))\n[(+ x) '(+ y)]\n{k (let z 1 (+ z))}");
+        let mut lint = PlusOne { found: vec![] };
+        lint.visit_node(&ast);
+        assert!(lint.found.iter().map(|s| s.to_string()).collect::<Vec<_>>() == vec!["1:6", "2:2", "3:13"]);
+    }
+
+    #[test]
+    fn test_visitor_mut() {
+        let ast = parse("(let x 1 (+ x [1 2] 'x))");
+        let mut renamed = ast.clone();
+        Rename(Symbol::intern("x"), Symbol::intern("y")).visit_node(&mut renamed);
+        assert!(renamed.to_string() == "(let y 1 (+ y [1 2] 'y))");
+        // The original is unchanged, and the parts which weren't renamed are
+        // shared.
+        assert!(ast.to_string() == "(let x 1 (+ x [1 2] 'x))");
+        let vector = |n: &Node| -> Rc<Vec<Node>> {
+            let mut v = None;
+            struct Find<'a>(&'a mut Option<Rc<Vec<Node>>>);
+            impl<'a> Visitor for Find<'a> {
+                fn visit_node(&mut self, node: &Node) {
+                    if let NodeKind::Vector(ref ns) = node.kind {
+                        *self.0 = Some(ns.clone());
+                    }
+                    walk_node(self, node)
+                }
+            }
+            Find(&mut v).visit_node(n);
+            v.unwrap()
+        };
+        assert!(Rc::ptr_eq(&vector(&ast), &vector(&renamed)));
+
+        // Nothing to rename, nothing is copied.
+        let mut same = ast.clone();
+        Rename(Symbol::intern("q"), Symbol::intern("y")).visit_node(&mut same);
+        match (&ast.kind, &same.kind) {
+            (&NodeKind::Program(ref a), &NodeKind::Program(ref b)) => assert!(Rc::ptr_eq(a, b)),
+            _ => unreachable!(),
+        }
+    }
+}